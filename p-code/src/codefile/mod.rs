@@ -0,0 +1,661 @@
+use crate::disassembler::{self, Endian, Instruction, Procedure};
+use crate::pascal_string::padded_to_string;
+
+// Which byte order to read the segment dictionary in.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ByteOrder {
+    /// Pick whichever byte order gives segments that fit in the file
+    Auto,
+    Le,
+    Be,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct CodeInfo {
+    address: u16, // in blocks
+    length: u16,  // in bytes, though the code takes whole blocks
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmentKind {
+    Linked,             // A ready-to-run program
+    HostSegment,        // The outer block of a Pascal program, if it has unresolved references
+    SegmentProcedure,   // Not used.
+    UnitSegment,        // A Unit, ready to be linked
+    SeparateSegment,    // Native-code segment
+    UnlinkedIntrinsic,  // An Intrinsic unit with unresolved references
+    LinkedIntrinsic,    // An Intrinsic unit
+    DataSegment,        // Data segment - data stored on the stack, used for some intrinsics
+    Unknown(u16),       // Anything else, from a damaged or vendor-specific file
+}
+
+impl TryFrom<u16> for SegmentKind {
+    type Error = u16;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        return match value {
+            0 => Ok(SegmentKind::Linked),
+            1 => Ok(SegmentKind::HostSegment),
+            2 => Ok(SegmentKind::SegmentProcedure),
+            3 => Ok(SegmentKind::UnitSegment),
+            4 => Ok(SegmentKind::SeparateSegment),
+            5 => Ok(SegmentKind::UnlinkedIntrinsic),
+            6 => Ok(SegmentKind::LinkedIntrinsic),
+            7 => Ok(SegmentKind::DataSegment),
+            _ => Err(value),
+        };
+    }
+}
+
+impl SegmentKind {
+    // Whether the linker still has work to do before the segment can run.
+    pub fn needs_linking(&self) -> bool {
+        return matches!(self, SegmentKind::HostSegment | SegmentKind::UnitSegment
+            | SegmentKind::SeparateSegment | SegmentKind::UnlinkedIntrinsic);
+    }
+}
+
+// Kinds of segment to pick out, by the names a user would type. Intrinsic
+// covers both the linked and unlinked kinds.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum KindFilter {
+    Linked,
+    Host,
+    Procedure,
+    Unit,
+    Separate,
+    Intrinsic,
+    UnlinkedIntrinsic,
+    LinkedIntrinsic,
+    Data,
+    Unknown,
+}
+
+impl KindFilter {
+    pub fn matches(&self, kind: SegmentKind) -> bool {
+        return match self {
+            KindFilter::Linked => kind == SegmentKind::Linked,
+            KindFilter::Host => kind == SegmentKind::HostSegment,
+            KindFilter::Procedure => kind == SegmentKind::SegmentProcedure,
+            KindFilter::Unit => kind == SegmentKind::UnitSegment,
+            KindFilter::Separate => kind == SegmentKind::SeparateSegment,
+            KindFilter::Intrinsic => matches!(kind, SegmentKind::UnlinkedIntrinsic | SegmentKind::LinkedIntrinsic),
+            KindFilter::UnlinkedIntrinsic => kind == SegmentKind::UnlinkedIntrinsic,
+            KindFilter::LinkedIntrinsic => kind == SegmentKind::LinkedIntrinsic,
+            KindFilter::Data => kind == SegmentKind::DataSegment,
+            KindFilter::Unknown => matches!(kind, SegmentKind::Unknown(_)),
+        };
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct SegmentDictionary {
+    code_info: [ CodeInfo; 16],     // one for each of 16 segments
+    seg_name: [[u8; 8]; 16],        // 8 charcters, space-padded
+    seg_kind: [u16; 16],            // one for each of 16 segments, see SegmentKind
+    text_addr: [u16; 16],           // For Units, this points to the Interface section
+    seg_info: [u16; 16],            // A bitfield for each segment
+    intrinsic_segments: u32,        // One bit for each segment in System.Library
+    // This is "library information", which is described by the Apple Pascal manual thus:
+    // Library information of undefined format occupies most of the remainder of the segment dictionary block.
+    // That's...great. I guess we'll figure that out when/if it comes up
+    library_info: [u8; 140],
+    copyright_string: [u8; 80],     // Copyright, as set by (*$C *), seems to be zero-terminated
+}
+
+pub const DICTIONARY_SIZE: usize = std::mem::size_of::<SegmentDictionary>();
+
+// The struct is read straight off the dictionary block, so padding or a
+// changed field would shift every field after it. Fail the build instead.
+const _: () = assert!(DICTIONARY_SIZE == 512, "SegmentDictionary must be exactly one block");
+
+// Byte ranges of the word-sized fields in the dictionary block, and the
+// width of the words in each.
+const DICTIONARY_WORDS: [(std::ops::Range<usize>, usize); 5] = [
+    (0..64, 2),     // code_info
+    (192..224, 2),  // seg_kind
+    (224..256, 2),  // text_addr
+    (256..288, 2),  // seg_info
+    (288..292, 4),  // intrinsic_segments
+];
+
+fn dictionary_word(bytes: &[u8], offset: usize, endian: Endian) -> u16 {
+    let pair = [bytes[offset], bytes[offset + 1]];
+    return match endian {
+        Endian::Little => u16::from_le_bytes(pair),
+        Endian::Big => u16::from_be_bytes(pair),
+    };
+}
+
+impl SegmentDictionary {
+    // The struct is read in host (little-endian) order, so a big-endian
+    // dictionary has its words swapped in a copy of the block first.
+    fn new(bytes: &[u8], endian: Endian) -> Self {
+        let mut block = bytes[..std::mem::size_of::<SegmentDictionary>()].to_vec();
+        if endian == Endian::Big {
+            for (range, width) in DICTIONARY_WORDS {
+                for word in block[range].chunks_mut(width) {
+                    word.reverse();
+                }
+            }
+        }
+        let directory_ptr = block.as_ptr() as *const SegmentDictionary;
+        let new_self = unsafe {directory_ptr.read_unaligned() };
+        return new_self;
+    }
+
+    // Score a byte order by how many segments it makes look sensible: a known
+    // kind, and a non-zero address with the code inside the file.
+    fn plausibility(bytes: &[u8], endian: Endian) -> usize {
+        let word = |offset: usize| dictionary_word(bytes, offset, endian) as usize;
+        let mut score = 0;
+        for s in 0..16 {
+            let address = word(s * 4);
+            let length = word(s * 4 + 2);
+            let kind = word(192 + s * 2);
+            if kind <= 7 {
+                score += 1;
+            }
+            if address != 0 && address * 512 + length <= bytes.len() {
+                score += 1;
+            }
+        }
+        return score;
+    }
+
+    fn endian(bytes: &[u8], order: ByteOrder) -> Endian {
+        return match order {
+            ByteOrder::Le => Endian::Little,
+            ByteOrder::Be => Endian::Big,
+            ByteOrder::Auto => {
+                if Self::plausibility(bytes, Endian::Big) > Self::plausibility(bytes, Endian::Little) {
+                    Endian::Big
+                } else {
+                    Endian::Little
+                }
+            }
+        };
+    }
+
+    fn kind(&self, segment: usize) -> SegmentKind {
+        let value = self.seg_kind[segment];
+        return SegmentKind::try_from(value).unwrap_or(SegmentKind::Unknown(value));
+    }
+}
+
+// One entry of the segment dictionary.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub index: usize,
+    pub name: String,
+    pub kind: SegmentKind,
+    pub address: usize,   // first block of the code
+    pub length: usize,    // length of the code in bytes
+    pub text_addr: u16,   // first block of the interface text, for units
+    pub seg_info: u16,
+}
+
+impl Segment {
+    // Offset of the code within the file.
+    pub fn start(&self) -> usize {
+        return self.address * 512;
+    }
+
+    pub fn end(&self) -> usize {
+        return self.start() + self.length;
+    }
+
+    // The blocks the code takes up, the last one partly filled.
+    pub fn blocks(&self) -> usize {
+        return self.length.div_ceil(512);
+    }
+
+    // Unused slots are all zeroes. Address 0 alone doesn't make a slot
+    // unused: that's the dictionary's block, so a segment there is damage,
+    // and it's shown rather than hidden.
+    pub fn is_empty(&self) -> bool {
+        return self.address == 0 && self.length == 0;
+    }
+
+    // The program's outer block, which the system runs as segment 1. Its
+    // slot in the dictionary varies, so it's told by kind and number.
+    pub fn is_main(&self) -> bool {
+        return self.number() == 1 && matches!(self.kind, SegmentKind::Linked | SegmentKind::HostSegment);
+    }
+
+    // The segment number the code was compiled as, from seg_info. For an
+    // intrinsic unit that's its slot in System.Library.
+    pub fn number(&self) -> usize {
+        return (self.seg_info & 0xff) as usize;
+    }
+
+    // The p-system version the segment was compiled for, from seg_info.
+    pub fn version(&self) -> u16 {
+        return self.seg_info >> 13;
+    }
+
+    pub fn is_intrinsic(&self) -> bool {
+        return matches!(self.kind, SegmentKind::LinkedIntrinsic | SegmentKind::UnlinkedIntrinsic | SegmentKind::DataSegment);
+    }
+}
+
+const LINK_ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkKind {
+    Unit,       // A unit the segment USES
+    External,   // An EXTERNAL procedure or function, from assembled code
+}
+
+// Something the linker has to find in another codefile.
+#[derive(Debug, Clone)]
+pub struct LinkReference {
+    pub name: String,
+    pub kind: LinkKind,
+}
+
+// A code file: the segment dictionary in its first block, then the segments.
+pub struct Codefile {
+    name: String,
+    bytes: Vec<u8>,
+    dictionary: SegmentDictionary,
+    endian: Endian, // the dictionary's, as given or detected
+}
+
+impl Codefile {
+    // A file that can't be read, or is too short to hold a dictionary, is an
+    // error for the caller to report.
+    pub fn new(name: &str, order: ByteOrder) -> Result<Self, String> {
+        let contents = std::fs::read(name).map_err(|e| format!("Can't read {name}: {e}"))?;
+        return Self::from_bytes(name, contents, order);
+    }
+
+    pub fn from_bytes(name: &str, bytes: Vec<u8>, order: ByteOrder) -> Result<Self, String> {
+        let size = DICTIONARY_SIZE;
+        if bytes.len() < size {
+            return Err(format!("The file is {} bytes, too short for a code file, which starts with a {size}-byte segment dictionary", bytes.len()));
+        }
+        let endian = SegmentDictionary::endian(&bytes, order);
+        let dictionary = SegmentDictionary::new(&bytes, endian);
+        return Ok(Self {
+            name: name.to_string(),
+            bytes,
+            dictionary,
+            endian,
+        });
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        return &self.bytes;
+    }
+
+    // The byte order the dictionary was read in. With ByteOrder::Auto it's
+    // whichever fitted the file better.
+    pub fn dictionary_endian(&self) -> Endian {
+        return self.endian;
+    }
+
+    // The library information area, at this offset in the dictionary block.
+    pub const LIBRARY_INFO_OFFSET: usize = 292;
+
+    pub fn library_info(&self) -> &[u8] {
+        return &self.dictionary.library_info;
+    }
+
+    // The System.Library segments the code uses, one bit per segment.
+    pub fn intrinsic_segments(&self) -> Vec<usize> {
+        return (0..32).filter(|b| self.dictionary.intrinsic_segments & (1 << b) != 0).collect();
+    }
+
+    // Whether the block could hold a segment dictionary of its own: at
+    // least one used segment, all of them in the file with known kinds.
+    pub fn looks_like_dictionary(&self, block: usize) -> bool {
+        let start = block * 512;
+        if block == 0 || start + DICTIONARY_SIZE > self.bytes.len() {
+            return false;
+        }
+        let dictionary = SegmentDictionary::new(&self.bytes[start..], Endian::Little);
+        let used: Vec<usize> = (0..16).filter(|s| dictionary.code_info[*s].address != 0).collect();
+        return !used.is_empty() && used.iter().all(|s| {
+            let code_info = dictionary.code_info[*s];
+            !matches!(dictionary.kind(*s), SegmentKind::Unknown(_))
+                && (code_info.address as usize * 512 + code_info.length as usize) <= self.bytes.len()
+        });
+    }
+
+    // Whether the dictionary describes at least one segment, all of them
+    // inside the file and of known kinds: enough to tell a code file from
+    // other data when nothing else says what it is.
+    pub fn is_plausible(&self) -> bool {
+        let segments = self.segments();
+        return !segments.is_empty() && segments.iter()
+            .all(|s| !matches!(s.kind, SegmentKind::Unknown(_)) && s.address > 0 && s.length > 0 && s.end() <= self.bytes.len());
+    }
+
+    pub fn copyright(&self) -> String {
+        return String::from_utf8_lossy(&self.dictionary.copyright_string).to_string();
+    }
+
+    // Any of the 16 dictionary entries, whether it's in use or not.
+    pub fn segment_at(&self, index: usize) -> Segment {
+        let code_info = self.dictionary.code_info[index];
+        return Segment {
+            index,
+            name: padded_to_string(&self.dictionary.seg_name[index]),
+            kind: self.dictionary.kind(index),
+            address: code_info.address as usize,
+            length: code_info.length as usize,
+            text_addr: self.dictionary.text_addr[index],
+            seg_info: self.dictionary.seg_info[index],
+        };
+    }
+
+    // The segments in use, skipping empty slots.
+    pub fn segments(&self) -> Vec<Segment> {
+        return (0..16).map(|s| self.segment_at(s)).filter(|s| !s.is_empty()).collect();
+    }
+
+    pub fn segment(&self, name: &str) -> Option<Segment> {
+        return self.segments().into_iter().find(|s| s.name == name);
+    }
+
+    // A segment whose code starts past the end of the file isn't damage so
+    // much as code kept somewhere else: an overlay or code pool loaded on
+    // demand. Where it comes from isn't recorded in the dictionary.
+    pub fn is_external(&self, index: usize) -> bool {
+        let segment = self.segment_at(index);
+        return !segment.is_empty() && segment.start() >= self.bytes.len();
+    }
+
+    // The bytes of a segment's code.
+    pub fn extract(&self, index: usize) -> Result<&[u8], String> {
+        let segment = self.segment_at(index);
+        if self.is_external(index) {
+            return Err(format!("code at {:#x?} is outside the file, so it's an external or overlay segment, skipping", segment.start()));
+        }
+        if segment.end() > self.bytes.len() {
+            return Err(format!("segment runs past the end of the file ({:#x?} > {:#x?}), skipping", segment.end(), self.bytes.len()));
+        }
+        return Ok(&self.bytes[segment.start()..segment.end()]);
+    }
+
+    // The bytes of a p-code segment and the byte order of its code, which
+    // comes from the segment's machine type rather than the dictionary.
+    pub fn pcode(&self, index: usize) -> Result<(&[u8], Endian), String> {
+        let code = self.extract(index)?;
+        let endian = match (self.dictionary.seg_info[index] & 0x0f00) >> 8 {
+            0 | 2 => Endian::Little,
+            1 => Endian::Big,
+            _ => return Err("native code, not disassembled".to_string()),
+        };
+        return Ok((code, endian));
+    }
+
+    // Every procedure in a p-code segment, with its instructions.
+    pub fn disassemble(&self, index: usize) -> Result<Vec<(Procedure, Vec<Instruction>)>, String> {
+        let (code, endian) = self.pcode(index)?;
+        let mut procs = disassembler::procedures(code, endian);
+        procs.sort_by_key(|p| p.enter_ic);
+        return Ok(procs.into_iter()
+            .map(|p| { let instructions = disassembler::disassemble_procedure(code, &p, endian); (p, instructions) })
+            .collect());
+    }
+
+    // For a unit, text_addr is the block where the text of its INTERFACE
+    // section starts. The text runs up to the segment's code.
+    pub fn interface_bytes(&self, index: usize) -> Result<&[u8], String> {
+        let segment = self.segment_at(index);
+        let start = segment.text_addr as usize * 512;
+        let end = segment.start();
+        if start == 0 || start >= end || end > self.bytes.len() {
+            return Err(format!("interface text {:#x?}..{:#x?} isn't inside the file, skipping", start, end));
+        }
+        return Ok(&self.bytes[start..end]);
+    }
+
+    // The text is in textfile format: 1024-byte pages of CR-terminated
+    // lines, with indents compressed to DLE and a count, padded with NULs.
+    // Apple Pascal writes just the pages; other compilers copy the whole
+    // textfile, header page and all, so a first page that isn't text but is
+    // followed by one that is gets skipped. Anything else is an error, and
+    // interface_bytes still has the raw bytes.
+    pub fn interface_text(&self, index: usize) -> Result<String, String> {
+        let bytes = self.interface_bytes(index)?;
+        let mut pages = bytes.chunks(TEXT_PAGE_SIZE);
+        if pages.next().is_some_and(is_text_page) {
+            return Ok(text_string(bytes));
+        }
+        if pages.next().is_some_and(is_text_page) {
+            return Ok(text_string(&bytes[TEXT_PAGE_SIZE..]));
+        }
+        return Err(format!("interface text at block {0} isn't in textfile format", self.segment_at(index).text_addr));
+    }
+
+    // Linker information follows the code of a segment that needs linking,
+    // from the next block on, in the code's byte order. Each entry is eight
+    // words: a name, a type and three words of detail. The types that are
+    // references are followed by the places the name is used, padded to a
+    // multiple of eight words. Type 0 ends the list. Only what's still to be
+    // found elsewhere is returned: the units it uses and its external
+    // routines; globals and constants are resolved within the program.
+    pub fn unresolved_references(&self, index: usize) -> Result<Vec<LinkReference>, String> {
+        let segment = self.segment_at(index);
+        let big = (segment.seg_info & 0x0f00) >> 8 == 1;
+        let word = |offset: usize| {
+            let pair = [self.bytes[offset], self.bytes[offset + 1]];
+            let value = if big { u16::from_be_bytes(pair) } else { u16::from_le_bytes(pair) };
+            return value as usize;
+        };
+        let mut offset = (segment.address + segment.blocks()) * 512;
+        if offset >= self.bytes.len() {
+            return Err("no linker information in the file".to_string());
+        }
+        let mut references: Vec<LinkReference> = Vec::new();
+        while offset + LINK_ENTRY_SIZE <= self.bytes.len() {
+            let name = padded_to_string(&self.bytes[offset..offset + 8]);
+            let kind = match word(offset + 8) {
+                0 => return Ok(references),
+                1 => Some(LinkKind::Unit),
+                9 | 10 => Some(LinkKind::External),
+                2..=14 => None,
+                other => return Err(format!("linker information at {offset:#x?} has an entry of unknown type {other}")),
+            };
+            let refs = match word(offset + 8) {
+                1..=5 | 13 | 14 => word(offset + 12).div_ceil(8) * LINK_ENTRY_SIZE,
+                _ => 0,
+            };
+            if let Some(kind) = kind && !references.iter().any(|r| r.name == name && r.kind == kind) {
+                references.push(LinkReference { name, kind });
+            }
+            offset += LINK_ENTRY_SIZE + refs;
+        }
+        return Err("linker information runs past the end of the file".to_string());
+    }
+
+    // Structural problems with the dictionary, one message for each.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.dictionary.copyright_string.contains(&0) {
+            problems.push("copyright string is not zero-terminated within 80 bytes".to_string());
+        }
+        for s in 0..16 {
+            let segment = self.segment_at(s);
+            let address = segment.address;
+            let length = segment.length;
+            let seg_info = segment.seg_info;
+            if let SegmentKind::Unknown(kind) = segment.kind {
+                problems.push(format!("segment {s}: kind {kind} is not a known segment kind (0-7)"));
+            }
+            if address == 0 && length == 0 {
+                continue;
+            }
+            let start = address * 512;
+            if address == 0 {
+                problems.push(format!("segment {s}: has length {length:#x} but address 0, which is the dictionary"));
+            } else if start + length > self.bytes.len() && !self.is_external(s) {
+                problems.push(format!("segment {s}: bytes {start:#x}..{:#x}, blocks {address}..{}, run past the end of the file ({:#x} bytes, {} blocks)",
+                    start + length, address + segment.blocks(), self.bytes.len(), self.bytes.len().div_ceil(512)));
+            }
+            let code_type = (seg_info & 0x0f00) >> 8;
+            if code_type > 9 {
+                problems.push(format!("segment {s}: seg_info code type {code_type} is not a known machine type"));
+            }
+            if seg_info & 0x1000 != 0 {
+                problems.push(format!("segment {s}: seg_info has unused bit 12 set ({seg_info:#06x})"));
+            }
+        }
+        return problems;
+    }
+}
+
+const TEXT_PAGE_SIZE: usize = 1024;
+
+// Whether a page could be part of a textfile: text, then only NULs.
+pub fn is_text_page(page: &[u8]) -> bool {
+    let end = page.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    if end == 0 || page[end - 1] != 0x0d {
+        return false;
+    }
+    let mut bytes = page[..end].iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            0x10 => {
+                if bytes.next().is_none_or(|count| *count < 32) {
+                    return false;
+                }
+            }
+            0x09 | 0x0c | 0x0d | 0x20..=0x7e => {}
+            _ => return false,
+        }
+    }
+    return true;
+}
+
+// Decode textfile pages: expand DLE-compressed indents and drop the NUL
+// padding. Lines end in CR, which is converted to LF unless keep_cr is set.
+// A damaged indent count, or a DLE with nothing after it, gives no spaces.
+pub fn text_from_pages(buffer: &[u8], keep_cr: bool) -> Vec<u8> {
+    let mut result = Vec::new();
+    let mut bytes = buffer.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            0x0d if !keep_cr => result.push(b'\n'),
+            0x10 => {
+                let count = bytes.next().map_or(0, |c| (*c as usize).saturating_sub(32));
+                result.resize(result.len() + count, b' ');
+            }
+            0 => continue,
+            _ => result.push(byte),
+        }
+    }
+    return result;
+}
+
+fn text_string(buffer: &[u8]) -> String {
+    return text_from_pages(buffer, false).iter().map(|b| *b as char).collect();
+}
+
+// The releases seg_info's version numbers stand for. 0 means the compiler
+// didn't record one.
+pub fn version_name(version: u16) -> Option<&'static str> {
+    return match version {
+        1 => Some("II.0"),
+        2 => Some("II.1"),
+        3 => Some("II.1 (Apple Pascal)"),
+        4 => Some("III.0"),
+        5 => Some("IV.0"),
+        6 => Some("IV.1"),
+        7 => Some("IV.2"),
+        _ => None,
+    };
+}
+
+// seg_info is a packed record: the segment number the code was compiled as
+// in bits 0-7, the machine type in bits 8-11, an unused bit 12, and the
+// p-system version in bits 13-15. That's every bit the Apple Pascal manual
+// documents; linkage is recorded in the segment kind instead.
+pub fn string_from_segment_info(segment_info: u16) -> String {
+    let segment = segment_info & 0xff;
+    let code_type = (segment_info & 0x0f00) >> 8;
+    let type_s = match code_type {
+        0 => "Unknown",
+        1 => "Pcode Big-endian",
+        2 => "Pcode Little-endian",
+        3 => "PDP-11",
+        4 => "8080",
+        5 => "Z80",
+        6 => "GA 440",
+        7 => "6502",
+        8 => "6800",
+        9 => "TI 9900",
+        _ => "Native code"
+    };
+    let version = (segment_info & 0xe000) >> 13;
+    let mut result = format!("[segment: {}, type: {}, version: {}", segment, type_s, version);
+    if segment_info & 0x1000 != 0 {
+        result.push_str(", unused bit 12 set");
+    }
+    result.push(']');
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A little-endian dictionary block holding these (name, address, length)
+    // segments in its first slots, followed by blocks for the code.
+    fn codefile(segments: &[(&str, u16, u16)], blocks: usize) -> Codefile {
+        let mut bytes = vec![0u8; blocks * 512];
+        bytes[64..192].fill(b' ');
+        for (s, (name, address, length)) in segments.iter().enumerate() {
+            bytes[s * 4..s * 4 + 2].copy_from_slice(&address.to_le_bytes());
+            bytes[s * 4 + 2..s * 4 + 4].copy_from_slice(&length.to_le_bytes());
+            bytes[64 + s * 8..64 + s * 8 + name.len()].copy_from_slice(name.as_bytes());
+            bytes[256 + s * 2..258 + s * 2].copy_from_slice(&(0xc200 | (s as u16 + 1)).to_le_bytes());
+        }
+        return Codefile::from_bytes("test.code", bytes, ByteOrder::Le).unwrap();
+    }
+
+    #[test]
+    fn hello_world_segment_bounds() {
+        let bytes = include_bytes!("../../../tests/HelloWorld.code").to_vec();
+        let codefile = Codefile::from_bytes("HelloWorld.code", bytes, ByteOrder::Auto).unwrap();
+        assert_eq!(codefile.dictionary_endian(), Endian::Little);
+        let segments = codefile.segments();
+        assert_eq!(segments.len(), 1);
+        let hello = &segments[0];
+        assert_eq!(hello.name, "HELLOWOR");
+        assert_eq!((hello.start(), hello.end(), hello.blocks()), (512, 512 + 0x70, 1));
+    }
+
+    // The second segment's code runs into a third block, and the first ends
+    // exactly on a block boundary.
+    #[test]
+    fn segment_bounds() {
+        let codefile = codefile(&[("HELLOWOR", 1, 512), ("SEG2", 2, 0x2c0)], 4);
+        let segments = codefile.segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[0].start(), segments[0].end(), segments[0].blocks()), (512, 1024, 1));
+        assert_eq!((segments[1].start(), segments[1].end(), segments[1].blocks()), (1024, 1024 + 0x2c0, 2));
+        assert_eq!(codefile.extract(1).unwrap().len(), 0x2c0);
+    }
+
+    // Only an all-zero slot is unused. Code at block 0 overlaps the
+    // dictionary, but it's still a segment, and so is one at block 1.
+    #[test]
+    fn empty_segments() {
+        let codefile = codefile(&[("DAMAGED", 0, 0x40), ("MAIN", 1, 0x70)], 2);
+        assert!(!codefile.segment_at(0).is_empty());
+        assert!(!codefile.segment_at(1).is_empty());
+        assert!(codefile.segment_at(2).is_empty());
+        let names: Vec<String> = codefile.segments().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["DAMAGED", "MAIN"]);
+    }
+}
@@ -0,0 +1,668 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+// A segment is a run of procedures followed by the procedure dictionary, which
+// lives at the very end of the segment and is read backwards:
+//   last word:      segment number (low byte), number of procedures (high byte)
+//   words before:   self-relative pointers to each procedure's attribute table
+// A procedure's attribute table is also read backwards from where it's pointed
+// at. The interpreter keeps that address in its JTAB register:
+//   JTAB+0: procedure number (low byte), lex level (high byte)
+//   JTAB-2: self-relative pointer to the first instruction (ENTERIC)
+//   JTAB-4: self-relative pointer to the return instruction (EXITIC)
+//   JTAB-6: parameter size in bytes
+//   JTAB-8: data size in bytes
+// Below that is the jump table used by jumps with a negative offset.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+fn read_word(bytes: &[u8], offset: usize, endian: Endian) -> Option<u16> {
+    let pair = [*bytes.get(offset)?, *bytes.get(offset + 1)?];
+    return Some(match endian {
+        Endian::Little => u16::from_le_bytes(pair),
+        Endian::Big => u16::from_be_bytes(pair),
+    });
+}
+
+// Self-relative pointers hold the distance back from their own location. The
+// subtraction wraps at 16 bits, so a pointer can also refer forwards.
+fn self_relative(bytes: &[u8], offset: usize, endian: Endian) -> Option<usize> {
+    let distance = read_word(bytes, offset, endian)?;
+    let target = (offset as u16).wrapping_sub(distance) as usize;
+    if target >= bytes.len() {
+        return None;
+    }
+    return Some(target);
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Procedure {
+    pub number: u8,
+    pub lex_level: i8,
+    pub jtab: usize,      // offset of the attribute table within the segment
+    pub enter_ic: usize,  // offset of the first instruction
+    pub exit_ic: usize,   // offset of the return instruction
+    pub param_size: u16,
+    pub data_size: u16,
+}
+
+// Parse the procedure dictionary at the end of a segment. Procedures whose
+// pointers lead outside the segment are skipped.
+pub fn procedures(segment: &[u8], endian: Endian) -> Vec<Procedure> {
+    let mut result = Vec::new();
+    if segment.len() < 2 {
+        return result;
+    }
+    let dict = segment.len() - 2;
+    let Some(header) = read_word(segment, dict, endian) else {
+        return result;
+    };
+    let num_procs = (header >> 8) as usize;
+    for p in 1..=num_procs {
+        let Some(pointer) = dict.checked_sub(2 * p) else {
+            break;
+        };
+        let Some(jtab) = self_relative(segment, pointer, endian) else {
+            continue;
+        };
+        let (Some(id), Some(enter_ic), Some(exit_ic), Some(param_size), Some(data_size)) = (
+            read_word(segment, jtab, endian),
+            jtab.checked_sub(2).and_then(|o| self_relative(segment, o, endian)),
+            jtab.checked_sub(4).and_then(|o| self_relative(segment, o, endian)),
+            jtab.checked_sub(6).and_then(|o| read_word(segment, o, endian)),
+            jtab.checked_sub(8).and_then(|o| read_word(segment, o, endian)),
+        ) else {
+            continue;
+        };
+        result.push(Procedure {
+            number: (id & 0xff) as u8,
+            lex_level: (id >> 8) as u8 as i8,
+            jtab,
+            enter_ic,
+            exit_ic,
+            param_size,
+            data_size,
+        });
+    }
+    return result;
+}
+
+// How the bytes following an opcode are laid out. The names follow the
+// operand notation used in the UCSD p-machine documentation.
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Implied,   // the operand is encoded in the opcode itself (SLDC, SLDL, ...)
+    None,
+    Ub,        // unsigned byte
+    Sb,        // signed byte jump offset
+    Db,        // "don't care" byte, normally a lex level
+    B,         // big: one byte if < 128, otherwise two bytes, high byte first
+    W,         // word, in the segment's byte sex
+    DbB,
+    UbB,
+    UbUb,
+    Lsa,       // UB length followed by that many characters
+    Ldc,       // UB word count followed by word-aligned words
+    Xjp,       // word-aligned case table
+    Compare,   // UB operand type; byte and word arrays add a B length
+}
+
+impl Format {
+    // The operands as the p-machine documentation writes them.
+    fn notation(&self) -> &'static str {
+        return match self {
+            Format::Implied | Format::None => "",
+            Format::Ub => "UB",
+            Format::Sb => "SB",
+            Format::Db => "DB",
+            Format::B => "B",
+            Format::W => "W",
+            Format::DbB => "DB, B",
+            Format::UbB => "UB, B",
+            Format::UbUb => "UB, UB",
+            Format::Lsa => "UB, chars",
+            Format::Ldc => "UB, words",
+            Format::Xjp => "W, W, UJP, table",
+            Format::Compare => "UB [, B]",
+        };
+    }
+}
+
+struct Opcode {
+    first: u8,
+    last: u8,
+    mnemonic: &'static str,
+    format: Format,
+    implied_base: i32, // operand of the first opcode in an Implied range
+    description: &'static str, // for annotated listings
+}
+
+const fn op(code: u8, mnemonic: &'static str, format: Format, description: &'static str) -> Opcode {
+    Opcode { first: code, last: code, mnemonic, format, implied_base: 0, description }
+}
+
+const fn range(first: u8, last: u8, mnemonic: &'static str, implied_base: i32, description: &'static str) -> Opcode {
+    Opcode { first, last, mnemonic, format: Format::Implied, implied_base, description }
+}
+
+// The Apple Pascal 1.x (UCSD II.1) instruction set.
+static OPCODES: &[Opcode] = &[
+    range(0x00, 0x7f, "SLDC", 0, "short load constant"),
+    op(0x80, "ABI", Format::None, "absolute value of integer"),
+    op(0x81, "ABR", Format::None, "absolute value of real"),
+    op(0x82, "ADI", Format::None, "add integers"),
+    op(0x83, "ADR", Format::None, "add reals"),
+    op(0x84, "LAND", Format::None, "logical and"),
+    op(0x85, "DIF", Format::None, "set difference"),
+    op(0x86, "DVI", Format::None, "divide integers"),
+    op(0x87, "DVR", Format::None, "divide reals"),
+    op(0x88, "CHK", Format::None, "check against subrange bounds"),
+    op(0x89, "FLO", Format::None, "float next-to-top integer"),
+    op(0x8a, "FLT", Format::None, "float top integer"),
+    op(0x8b, "INN", Format::None, "set membership"),
+    op(0x8c, "INT", Format::None, "set intersection"),
+    op(0x8d, "LOR", Format::None, "logical or"),
+    op(0x8e, "MODI", Format::None, "modulo integers"),
+    op(0x8f, "MPI", Format::None, "multiply integers"),
+    op(0x90, "MPR", Format::None, "multiply reals"),
+    op(0x91, "NGI", Format::None, "negate integer"),
+    op(0x92, "NGR", Format::None, "negate real"),
+    op(0x93, "LNOT", Format::None, "logical not"),
+    op(0x94, "SRS", Format::None, "build subrange set"),
+    op(0x95, "SBI", Format::None, "subtract integers"),
+    op(0x96, "SBR", Format::None, "subtract reals"),
+    op(0x97, "SGS", Format::None, "build singleton set"),
+    op(0x98, "SQI", Format::None, "square integer"),
+    op(0x99, "SQR", Format::None, "square real"),
+    op(0x9a, "STO", Format::None, "store indirect word"),
+    op(0x9b, "IXS", Format::None, "index string, checking the bounds"),
+    op(0x9c, "UNI", Format::None, "set union"),
+    op(0x9d, "LDE", Format::UbB, "load extended word"),
+    op(0x9e, "CSP", Format::Ub, "call standard procedure"),
+    op(0x9f, "LDCN", Format::None, "load constant nil"),
+    op(0xa0, "ADJ", Format::Ub, "adjust set to a length"),
+    op(0xa1, "FJP", Format::Sb, "jump if false"),
+    op(0xa2, "INC", Format::B, "increment pointer by a field offset"),
+    op(0xa3, "IND", Format::B, "load word at pointer plus offset"),
+    op(0xa4, "IXA", Format::B, "index array"),
+    op(0xa5, "LAO", Format::B, "load global address"),
+    op(0xa6, "LSA", Format::Lsa, "load string constant address"),
+    op(0xa7, "LAE", Format::UbB, "load extended address"),
+    op(0xa8, "MOV", Format::B, "move words"),
+    op(0xa9, "LDO", Format::B, "load global word"),
+    op(0xaa, "SAS", Format::Ub, "string assign"),
+    op(0xab, "SRO", Format::B, "store global word"),
+    op(0xac, "XJP", Format::Xjp, "case jump"),
+    op(0xad, "RNP", Format::Db, "return from non-base procedure"),
+    op(0xae, "CIP", Format::Ub, "call intermediate procedure"),
+    op(0xaf, "EQU", Format::Compare, "compare equal"),
+    op(0xb0, "GEQ", Format::Compare, "compare greater or equal"),
+    op(0xb1, "GRT", Format::Compare, "compare greater"),
+    op(0xb2, "LDA", Format::DbB, "load intermediate address"),
+    op(0xb3, "LDC", Format::Ldc, "load multiple-word constant"),
+    op(0xb4, "LEQ", Format::Compare, "compare less or equal"),
+    op(0xb5, "LES", Format::Compare, "compare less"),
+    op(0xb6, "LOD", Format::DbB, "load intermediate word"),
+    op(0xb7, "NEQ", Format::Compare, "compare not equal"),
+    op(0xb8, "STR", Format::DbB, "store intermediate word"),
+    op(0xb9, "UJP", Format::Sb, "unconditional jump"),
+    op(0xba, "LDP", Format::None, "load packed field"),
+    op(0xbb, "STP", Format::None, "store packed field"),
+    op(0xbc, "LDM", Format::Ub, "load multiple words"),
+    op(0xbd, "STM", Format::Ub, "store multiple words"),
+    op(0xbe, "LDB", Format::None, "load byte"),
+    op(0xbf, "STB", Format::None, "store byte"),
+    op(0xc0, "IXP", Format::UbUb, "index packed array"),
+    op(0xc1, "RBP", Format::Db, "return from base procedure"),
+    op(0xc2, "CBP", Format::Ub, "call base procedure"),
+    op(0xc3, "EQUI", Format::None, "integers equal"),
+    op(0xc4, "GEQI", Format::None, "integer greater or equal"),
+    op(0xc5, "GRTI", Format::None, "integer greater"),
+    op(0xc6, "LLA", Format::B, "load local address"),
+    op(0xc7, "LDCI", Format::W, "load one-word constant"),
+    op(0xc8, "LEQI", Format::None, "integer less or equal"),
+    op(0xc9, "LESI", Format::None, "integer less"),
+    op(0xca, "LDL", Format::B, "load local word"),
+    op(0xcb, "NEQI", Format::None, "integers not equal"),
+    op(0xcc, "STL", Format::B, "store local word"),
+    op(0xcd, "CXP", Format::UbUb, "call external procedure"),
+    op(0xce, "CLP", Format::Ub, "call local procedure"),
+    op(0xcf, "CGP", Format::Ub, "call global procedure"),
+    op(0xd1, "STE", Format::UbB, "store extended word"),
+    op(0xd3, "EFJ", Format::Sb, "jump if integers not equal"),
+    op(0xd4, "NFJ", Format::Sb, "jump if integers equal"),
+    op(0xd5, "BPT", Format::B, "breakpoint"),
+    op(0xd6, "XIT", Format::None, "exit the system"),
+    op(0xd7, "NOP", Format::None, "no operation"),
+    range(0xd8, 0xe7, "SLDL", 1, "short load local word"),
+    range(0xe8, 0xf7, "SLDO", 1, "short load global word"),
+    range(0xf8, 0xff, "SIND", 0, "short load word at pointer plus offset"),
+];
+
+// Print the opcode table the disassembler decodes with, one line per entry.
+// A range of opcodes with the operand built in shows the operands it covers.
+pub fn print_opcodes() {
+    println!("{:7} {:6} {:16} Description", "Opcode", "Name", "Operands");
+    for opcode in OPCODES {
+        let (codes, operands) = if opcode.first == opcode.last {
+            (format!("{:02x}", opcode.first), opcode.format.notation().to_string())
+        } else {
+            let last = opcode.implied_base + (opcode.last - opcode.first) as i32;
+            (format!("{:02x}..{:02x}", opcode.first, opcode.last), format!("{0}..{last} implied", opcode.implied_base))
+        };
+        println!("{codes:7} {:6} {operands:16} {}", opcode.mnemonic, opcode.description);
+    }
+    let known: usize = OPCODES.iter().map(|o| (o.last - o.first) as usize + 1).sum();
+    println!("{known} of 256 opcodes known");
+}
+
+fn lookup(code: u8) -> Option<&'static Opcode> {
+    return OPCODES.iter().find(|o| o.first <= code && code <= o.last);
+}
+
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Number(i32),
+    Target(usize),      // a jump destination within the segment
+    Table(Vec<usize>),  // XJP case destinations, lowest case first
+    String(Vec<u8>),    // LSA string literal
+    Words(Vec<u16>),    // LDC constant, in the order the words appear in the code
+}
+
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub offset: usize,
+    pub length: usize,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+}
+
+impl Instruction {
+    fn targets(&self) -> Vec<usize> {
+        let mut result = Vec::new();
+        for operand in &self.operands {
+            match operand {
+                Operand::Target(t) => result.push(*t),
+                Operand::Table(targets) => result.extend(targets),
+                _ => {}
+            }
+        }
+        return result;
+    }
+}
+
+struct Decoder<'a> {
+    segment: &'a [u8],
+    endian: Endian,
+    jtab: usize,
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let b = *self.segment.get(self.pos)?;
+        self.pos += 1;
+        return Some(b);
+    }
+
+    fn big(&mut self) -> Option<i32> {
+        let b0 = self.byte()? as i32;
+        if b0 & 0x80 == 0 {
+            return Some(b0);
+        }
+        let b1 = self.byte()? as i32;
+        return Some(((b0 & 0x7f) << 8) | b1);
+    }
+
+    fn word(&mut self) -> Option<u16> {
+        let w = read_word(self.segment, self.pos, self.endian)?;
+        self.pos += 2;
+        return Some(w);
+    }
+
+    fn align(&mut self) {
+        self.pos += self.pos & 1;
+    }
+
+    // Short jumps are relative to the following instruction. Negative
+    // offsets instead index back from JTAB into the jump table, whose entries
+    // are self-relative pointers to the real destination.
+    fn jump(&mut self) -> Option<usize> {
+        let offset = self.byte()? as i8;
+        if offset >= 0 {
+            return Some(self.pos + offset as usize);
+        }
+        let entry = self.jtab.checked_sub(offset.unsigned_abs() as usize)?;
+        return self_relative(self.segment, entry, self.endian);
+    }
+
+    fn operands(&mut self, opcode: &Opcode, code: u8) -> Option<Vec<Operand>> {
+        use Operand::*;
+        let operands = match opcode.format {
+            Format::Implied => vec![Number(opcode.implied_base + (code - opcode.first) as i32)],
+            Format::None => vec![],
+            Format::Ub | Format::Db => vec![Number(self.byte()? as i32)],
+            Format::Sb => vec![Target(self.jump()?)],
+            Format::B => vec![Number(self.big()?)],
+            Format::W => vec![Number(self.word()? as i16 as i32)],
+            Format::DbB | Format::UbB => vec![Number(self.byte()? as i32), Number(self.big()?)],
+            Format::UbUb => vec![Number(self.byte()? as i32), Number(self.byte()? as i32)],
+            Format::Lsa => {
+                let len = self.byte()? as usize;
+                let chars = self.segment.get(self.pos..self.pos + len)?.to_vec();
+                self.pos += len;
+                vec![String(chars)]
+            }
+            Format::Ldc => {
+                let count = self.byte()?;
+                self.align();
+                let mut words = Vec::new();
+                for _ in 0..count {
+                    words.push(self.word()?);
+                }
+                vec![Number(count as i32), Words(words)]
+            }
+            Format::Xjp => {
+                self.align();
+                let min = self.word()? as i16;
+                let max = self.word()? as i16;
+                // The out-of-range case is an ordinary UJP ahead of the table.
+                if self.byte()? != 0xb9 {
+                    return None;
+                }
+                let default = self.jump()?;
+                let mut targets = Vec::new();
+                for _ in min..=max {
+                    let entry = self.pos;
+                    self.word()?;
+                    targets.push(self_relative(self.segment, entry, self.endian)?);
+                }
+                vec![Number(min as i32), Number(max as i32), Target(default), Table(targets)]
+            }
+            Format::Compare => {
+                let kind = self.byte()?;
+                if kind == 10 || kind == 12 {
+                    vec![Number(kind as i32), Number(self.big()?)]
+                } else {
+                    vec![Number(kind as i32)]
+                }
+            }
+        };
+        return Some(operands);
+    }
+
+    fn next(&mut self) -> Instruction {
+        let offset = self.pos;
+        let code = self.segment[offset];
+        self.pos += 1;
+        let decoded = lookup(code).and_then(|opcode| {
+            Some((opcode.mnemonic, self.operands(opcode, code)?))
+        });
+        let (mnemonic, operands) = decoded.unwrap_or_else(|| {
+            // Unknown or truncated; resynchronise on the next byte.
+            self.pos = offset + 1;
+            ("???", vec![Operand::Number(code as i32)])
+        });
+        return Instruction {
+            offset,
+            length: self.pos - offset,
+            mnemonic,
+            operands,
+        };
+    }
+}
+
+// A procedure's instructions, from its entry point through its return
+// instruction, decoded one at a time as they're asked for.
+pub struct Instructions<'a> {
+    decoder: Decoder<'a>,
+    end: usize,
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Instruction> {
+        if self.decoder.pos > self.end || self.decoder.pos >= self.decoder.segment.len() {
+            return None;
+        }
+        return Some(self.decoder.next());
+    }
+}
+
+pub fn instructions<'a>(segment: &'a [u8], procedure: &Procedure, endian: Endian) -> Instructions<'a> {
+    return Instructions {
+        decoder: Decoder { segment, endian, jtab: procedure.jtab, pos: procedure.enter_ic },
+        end: procedure.exit_ic.min(procedure.jtab),
+    };
+}
+
+// Instructions from start through the one that begins at end, ignoring the
+// procedure dictionary. With no procedure there's no jump table, so jumps
+// through it can't be decoded and show as unknown bytes.
+pub fn instructions_between(segment: &[u8], start: usize, end: usize, endian: Endian) -> Instructions<'_> {
+    return Instructions {
+        decoder: Decoder { segment, endian, jtab: 0, pos: start },
+        end,
+    };
+}
+
+pub fn disassemble_procedure(segment: &[u8], procedure: &Procedure, endian: Endian) -> Vec<Instruction> {
+    return instructions(segment, procedure, endian).collect();
+}
+
+// Every instruction in the segment, with the procedure it's in, procedures
+// in the order their code appears. Only the procedure dictionary is read up
+// front, so memory stays small however big the segment is.
+pub fn disassemble_iter(segment: &[u8], endian: Endian) -> impl Iterator<Item = (Procedure, Instruction)> + '_ {
+    let mut procs = procedures(segment, endian);
+    procs.sort_by_key(|p| p.enter_ic);
+    return procs.into_iter().flat_map(move |p| instructions(segment, &p, endian).map(move |i| (p, i)));
+}
+
+fn label(offset: usize) -> String {
+    return format!("L_{:04x}", offset);
+}
+
+// Quote a string literal Pascal-style, doubling any embedded quotes.
+// Unprintable characters are shown as hex escapes.
+fn quote(chars: &[u8]) -> String {
+    let mut result = String::from("'");
+    for c in chars {
+        match *c {
+            b'\'' => result.push_str("''"),
+            32..=126 => result.push(*c as char),
+            _ => result.push_str(&format!("\\x{:02x}", c)),
+        }
+    }
+    result.push('\'');
+    return result;
+}
+
+fn format_operands(instruction: &Instruction) -> String {
+    let mut parts = Vec::new();
+    for operand in &instruction.operands {
+        match operand {
+            Operand::Number(n) => parts.push(n.to_string()),
+            Operand::Target(t) => parts.push(label(*t)),
+            Operand::Table(targets) => {
+                let labels: Vec<String> = targets.iter().map(|t| label(*t)).collect();
+                parts.push(format!("[{}]", labels.join(", ")));
+            }
+            Operand::String(chars) => parts.push(quote(chars)),
+            Operand::Words(words) => {
+                let hex: Vec<String> = words.iter().map(|w| format!("${:04x}", w)).collect();
+                parts.push(format!("[{}]", hex.join(", ")));
+            }
+        }
+    }
+    let mut result = parts.join(", ");
+    // LDC pushes its words in code order, so the first one ends up highest on
+    // the stack. A two-word constant is usually a REAL.
+    if let [_, Operand::Words(words)] = instruction.operands.as_slice() && words.len() == 2 {
+        let real = f32::from_bits((words[0] as u32) << 16 | words[1] as u32);
+        result.push_str(&format!("  ; real {}", real));
+    }
+    return result;
+}
+
+fn format_bytes(segment: &[u8], instruction: &Instruction) -> String {
+    let shown = instruction.length.min(6);
+    let mut result = String::new();
+    for b in &segment[instruction.offset..instruction.offset + shown] {
+        result.push_str(&format!("{:02x} ", b));
+    }
+    if shown < instruction.length {
+        result.push_str("..");
+    }
+    return result;
+}
+
+// Print every procedure in the segment. Jump destinations are collected first
+// so they can be given labels, which are then used in place of raw offsets.
+// With only, just that procedure is printed.
+pub fn print_segment(out: &mut impl Write, segment: &[u8], endian: Endian, listing: &Listing, only: Option<usize>) -> io::Result<()> {
+    let mut procs = procedures(segment, endian);
+    procs.sort_by_key(|p| p.enter_ic);
+    if let Some(number) = only && !procs.iter().any(|p| p.number as usize == number) {
+        writeln!(out, "No procedure {number} in this segment")?;
+        return Ok(());
+    }
+    for procedure in procs {
+        if only.is_some_and(|n| n != procedure.number as usize) {
+            continue;
+        }
+        writeln!(out, "Procedure {} (lex level {}, parameters: {} bytes, data: {} bytes)",
+            procedure.number, procedure.lex_level, procedure.param_size, procedure.data_size)?;
+        print_instructions(out, segment, listing, || instructions(segment, &procedure, endian))?;
+        writeln!(out)?;
+    }
+    return Ok(());
+}
+
+// Print bytes start..end of the segment as instructions, for when the
+// procedure dictionary can't be trusted.
+pub fn print_range(out: &mut impl Write, segment: &[u8], endian: Endian, listing: &Listing, start: usize, end: usize) -> io::Result<()> {
+    let end = end.min(segment.len());
+    if start >= end {
+        writeln!(out, "Nothing to disassemble: offset {start:#06x} is not before {end:#06x}, and the segment is {:#06x} bytes", segment.len())?;
+        return Ok(());
+    }
+    writeln!(out, "Bytes {start:#06x}..{end:#06x}, without the procedure dictionary")?;
+    print_instructions(out, segment, listing, || instructions_between(segment, start, end - 1, endian))?;
+    writeln!(out)?;
+    return Ok(());
+}
+
+// Labels have to be known before the first jump to them is printed, so the
+// instructions are decoded twice rather than kept.
+fn print_instructions<'a>(out: &mut impl Write, segment: &[u8], listing: &Listing, decode: impl Fn() -> Instructions<'a>) -> io::Result<()> {
+    let targets: BTreeSet<usize> = decode().flat_map(|i| i.targets()).collect();
+    for instruction in decode() {
+        let instruction = &instruction;
+        if targets.contains(&instruction.offset) {
+            writeln!(out, "{}:", label(instruction.offset))?;
+        }
+        let mut line = String::from("    ");
+        // The description column moves left with the columns left out.
+        let mut width = 56;
+        if listing.offsets {
+            line.push_str(&format!("{:04x}: ", instruction.offset));
+        } else {
+            width -= 6;
+        }
+        if listing.bytes {
+            line.push_str(&format!("{:20} ", format_bytes(segment, instruction)));
+        } else {
+            width -= 21;
+        }
+        line.push_str(&format!("{:5} {}", instruction.mnemonic, format_operands(instruction)));
+        match lookup(segment[instruction.offset]).filter(|_| listing.annotate) {
+            Some(opcode) => writeln!(out, "{:width$} ; {}", line.trim_end(), opcode.description)?,
+            None => writeln!(out, "{}", line.trim_end())?,
+        }
+    }
+    return Ok(());
+}
+
+// What the text listing shows alongside each instruction.
+#[derive(Debug, Clone, Copy)]
+pub struct Listing {
+    pub offsets: bool,  // the instruction's offset in the segment
+    pub bytes: bool,    // its bytes in hex
+    pub annotate: bool, // what its opcode does
+}
+
+// How disassembly is written out.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Syntax {
+    /// Offsets and bytes alongside each instruction, for reading
+    Text,
+    /// Source for a p-code assembler; see print_segment_asm
+    Asm,
+}
+
+// Print the segment as assembler source. One statement per line, and
+// anything after a ';' is a comment:
+//   .segment INDEX, NAME        the segment's slot and name in the dictionary
+//   .segnum N                   segment number from the procedure dictionary
+//   .proc N, LEX, PARAMS, DATA  a procedure: its number, lex level, and
+//                               parameter and data sizes in bytes
+//   .exit                       the next instruction is the procedure's EXITIC
+//   .endproc
+//   .byte $XX                   a byte that isn't a known instruction
+//   .endsegment
+//   L_XXXX:                     label, named by offset within the segment
+//       MNEMONIC OPERANDS       operands as the decoder found them, in order:
+//                               decimal numbers, labels for jump destinations,
+//                               [L_a, L_b] for a case table, [$XXXX, ...] for
+//                               constant words, and Pascal-quoted strings
+// The segment and endsegment lines are left to the caller, which knows the
+// dictionary. Procedures come in code order, and the procedure dictionary
+// is left for the assembler to rebuild from the .proc lines, as are the
+// jump table and any padding for word alignment.
+pub fn print_segment_asm(out: &mut impl Write, segment: &[u8], endian: Endian, only: Option<usize>) -> io::Result<()> {
+    let mut procs = procedures(segment, endian);
+    procs.sort_by_key(|p| p.enter_ic);
+    if let Some(number) = only && !procs.iter().any(|p| p.number as usize == number) {
+        writeln!(out, "; No procedure {number} in this segment")?;
+        return Ok(());
+    }
+    if let Some(number) = segment.len().checked_sub(2).and_then(|o| read_word(segment, o, endian)) {
+        writeln!(out, "        .segnum {}", number & 0xff)?;
+    }
+    for procedure in procs {
+        if only.is_some_and(|n| n != procedure.number as usize) {
+            continue;
+        }
+        writeln!(out, "        .proc {}, {}, {}, {}", procedure.number, procedure.lex_level, procedure.param_size, procedure.data_size)?;
+        let targets: BTreeSet<usize> = instructions(segment, &procedure, endian).flat_map(|i| i.targets()).collect();
+        for instruction in instructions(segment, &procedure, endian) {
+            if targets.contains(&instruction.offset) {
+                writeln!(out, "{}:", label(instruction.offset))?;
+            }
+            if instruction.offset == procedure.exit_ic {
+                writeln!(out, "        .exit")?;
+            }
+            if instruction.mnemonic == "???" {
+                writeln!(out, "        .byte ${:02x}", segment[instruction.offset])?;
+            } else {
+                let line = format!("        {:5} {}", instruction.mnemonic, format_operands(&instruction));
+                writeln!(out, "{}", line.trim_end())?;
+            }
+        }
+        writeln!(out, "        .endproc")?;
+    }
+    return Ok(());
+}
+
+// Add the number of times each mnemonic is used in the segment to counts.
+pub fn count_opcodes(segment: &[u8], endian: Endian, counts: &mut BTreeMap<&'static str, usize>) {
+    for (_, instruction) in disassemble_iter(segment, endian) {
+        *counts.entry(instruction.mnemonic).or_insert(0) += 1;
+    }
+}
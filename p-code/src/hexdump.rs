@@ -0,0 +1,33 @@
+// The hex dump line both tools print: an offset, the bytes in hex, then the
+// bytes as characters between bars. How each byte is shown is up to the
+// caller, so p-filer can colour bytes and pick a character set.
+
+pub const LINE_LEN: usize = 16;
+
+pub fn line(offset: usize, bytes: &[u8], hex: impl Fn(u8) -> String, chars: impl Fn(u8) -> String) -> String {
+    let mut result = format!("{:06x}  ", offset);
+    for byte in bytes {
+        result.push_str(&hex(*byte));
+        result.push(' ');
+    }
+    // A short last line still lines its characters up with the others.
+    for _ in bytes.len()..LINE_LEN {
+        result.push_str("   ");
+    }
+    result.push_str("  |");
+    for byte in bytes {
+        result.push_str(&chars(*byte));
+    }
+    result.push('|');
+    return result;
+}
+
+// Printable ASCII, and '.' for everything else.
+pub fn ascii(byte: u8) -> String {
+    let c = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+    return c.to_string();
+}
+
+pub fn hex(byte: u8) -> String {
+    return format!("{:02x}", byte);
+}
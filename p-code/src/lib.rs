@@ -0,0 +1,6 @@
+#![allow(clippy::needless_return)]
+pub mod codefile;
+pub mod disassembler;
+pub mod hexdump;
+pub mod pascal_string;
+pub mod unit_stub;
@@ -1,130 +1,581 @@
-use clap::{Parser, Subcommand};
+#![allow(clippy::needless_return)]
+use clap::{Args, Parser, Subcommand};
+use p_code::codefile::{ByteOrder, Codefile, KindFilter, LinkKind, Segment, SegmentKind, string_from_segment_info};
+use p_code::{disassembler, hexdump, unit_stub};
+use p_code::disassembler::{Endian, Listing, Syntax};
+use std::io::Write;
 
 /// A command-file tool for manipulating UCSD pascal object files
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct MainArgs {
-    /// Name of disk image to use
+    /// Name of the code file to use; every command but opcodes needs one
     #[arg(short, long)]
-    code_file: String,
+    code_file: Option<String>,
+    /// Byte order of the segment dictionary words
+    #[arg(long, value_enum, default_value_t = ByteOrder::Auto)]
+    endian: ByteOrder,
+    /// Refuse to work on a code file that verify-codefile finds any problem with
+    #[arg(long, global = true)]
+    strict: bool,
     #[command(subcommand)]
     command: Commands
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    List,
-    Disassemble,
-}
-
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-struct CodeInfo {
-    address: u16,
-    length: u16,
-}
-
-#[repr(u16)]
-#[derive(Debug, Clone, Copy)]
-#[allow(dead_code)]
-enum SegmentKind {
-    Linked,             // A ready-to-run program
-    HostSegment,        // The outer block of a Pascal program, if it has unresolved references
-    SegmentProcedure,   // Not used.
-    UnitSegment,        // A Unit, ready to be linked
-    SeparateSegment,    // Native-code segment
-    UnlinkedIntrinsic,  // An Intrinsic unit with unresolved references
-    LinkedIntrinsic,    // An Intrinsic unit
-    DataSegment         // Data segment - data stored on the stack, used for some intrinsics
-}
-
-#[derive(Debug)]
-#[repr(C)]
-struct SegmentDictionary {
-    code_info: [ CodeInfo; 16],     // one for each of 16 segments
-    seg_name: [[u8; 8]; 16],        // 8 charcters, space-padded
-    seg_kind: [SegmentKind; 16],    // one for each of 16 segments
-    text_addr: [u16; 16],           // For Units, this points to the Interface section
-    seg_info: [u16; 16],            // A bitfield for each segment
-    intrinsic_segments: u32,        // One bit for each segment in System.Library
-    // This is "library information", which is described by the Apple Pascal manual thus:
-    // Library information of undefined format occupies most of the remainder of the segment dictionary block.
-    // That's...great. I guess we'll figure that out when/if it comes up
-    library_info: [u8; 140],
-    copyright_string: [u8; 80],     // Copyright, as set by (*$C *), seems to be zero-terminated
-}
-
-impl SegmentDictionary {
-    fn new(bytes: &[u8]) -> Self {
-        let directory_ptr = bytes.as_ptr() as *const SegmentDictionary;
-        let new_self = unsafe {directory_ptr.read_unaligned() };
-        return new_self;
-    }
+    List {
+        /// Only segments of these kinds, comma-separated, e.g. unit,intrinsic
+        #[arg(long, alias = "segment-kind", value_enum, value_delimiter = ',')]
+        kind: Vec<KindFilter>,
+    },
+    Disassemble(DisassembleArgs),
+    /// Check the segment dictionary for structural problems
+    VerifyCodefile,
+    /// Draw where each segment sits in the file
+    SegmentMap {
+        /// Length of the bars, in characters
+        #[arg(long, default_value_t = 64)]
+        width: usize,
+    },
+    /// Count how often each opcode is used
+    Profile {
+        /// Only this segment, instead of the whole file
+        #[arg(value_parser = parse_segment)]
+        segment: Option<usize>,
+    },
+    /// Show the library information area of the dictionary, with guesses
+    LibraryInfo,
+    /// List the System.Library segments the code relies on
+    SegmentDeps {
+        /// Library to look the segment numbers up in, e.g. SYSTEM.LIBRARY
+        #[arg(long)]
+        library: Option<String>,
+    },
+    /// Hex dump the bytes of a segment
+    Dump {
+        #[arg(long, value_parser = parse_segment)]
+        segment: usize,
+        /// First byte to show, counting from the start of the segment
+        #[arg(long, default_value_t = 0, value_parser = parse_offset)]
+        from: usize,
+        /// Byte to stop before, counting from the start of the segment
+        #[arg(long, value_parser = parse_offset)]
+        to: Option<usize>,
+        /// Show offsets from the start of the file instead
+        #[arg(long)]
+        absolute: bool,
+    },
+    /// Print the table of opcodes the disassembler knows
+    Opcodes,
+    /// Print the INTERFACE text of unit segments
+    Interface {
+        /// Only this segment, instead of every unit
+        #[arg(value_parser = parse_segment)]
+        segment: Option<usize>,
+        /// Print each unit as a stub that compiles, with empty routine bodies
+        #[arg(long)]
+        export_interface: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+struct DisassembleArgs {
+    /// Say what each instruction's opcode does
+    #[arg(long)]
+    annotate: bool,
+    /// Only this segment, instead of the whole file
+    #[arg(long, value_parser = parse_segment)]
+    segment: Option<usize>,
+    /// Only this procedure of the segment
+    #[arg(long, requires = "segment")]
+    procedure: Option<usize>,
+    /// Write it for reading, or as source for a p-code assembler
+    #[arg(long, value_enum, default_value_t = Syntax::Text, conflicts_with = "annotate")]
+    format: Syntax,
+    /// Decode from this byte offset in the segment, ignoring its
+    /// procedure dictionary
+    #[arg(long, requires = "segment", conflicts_with_all = ["procedure", "format"], value_parser = parse_offset)]
+    start: Option<usize>,
+    /// Number of bytes to decode from --start, instead of to the end of
+    /// the segment
+    #[arg(long, requires = "start", value_parser = parse_offset)]
+    len: Option<usize>,
+    /// Write the disassembly to this file instead of standard output
+    #[arg(long, short)]
+    output: Option<String>,
+    /// Leave out each instruction's offset
+    #[arg(long, conflicts_with = "format")]
+    no_offsets: bool,
+    /// Leave out each instruction's bytes
+    #[arg(long, conflicts_with = "format")]
+    no_bytes: bool,
 }
 
 fn main() {
-    println!("size of SegmentDictionary is {}", std::mem::size_of::<SegmentDictionary>() );
     let args = MainArgs::parse();
-    let file_name = args.code_file;
+    if let Commands::Opcodes = args.command {
+        disassembler::print_opcodes();
+        return;
+    }
+    let Some(name) = &args.code_file else {
+        eprintln!("No code file given: use --code-file");
+        std::process::exit(1);
+    };
+    let codefile = open(name, args.endian);
+    if args.endian == ByteOrder::Auto && codefile.dictionary_endian() == Endian::Big {
+        eprintln!("Segment dictionary appears to be big-endian");
+    }
+    if args.strict && !matches!(args.command, Commands::VerifyCodefile) {
+        let problems = codefile.problems();
+        if !problems.is_empty() {
+            for problem in &problems {
+                eprintln!("  {problem}");
+            }
+            eprintln!("{} problem(s) found, and --strict is on", problems.len());
+            std::process::exit(1);
+        }
+    }
     match &args.command {
-        Commands::List => list(file_name),
-        Commands::Disassemble => disassemble(file_name),
+        Commands::List { kind } => list(&codefile, kind),
+        Commands::Disassemble(args) => disassemble(&codefile, args),
+        Commands::VerifyCodefile => verify_codefile(&codefile),
+        Commands::SegmentMap { width } => segment_map(&codefile, *width),
+        Commands::Profile { segment } => profile(&codefile, *segment),
+        Commands::LibraryInfo => library_info(&codefile),
+        Commands::Interface { segment, export_interface } => interface(&codefile, *segment, *export_interface),
+        Commands::Opcodes => unreachable!("opcodes doesn't open a code file"),
+        Commands::Dump { segment, from, to, absolute } => dump(&codefile, *segment, *from, *to, *absolute),
+        Commands::SegmentDeps { library } => {
+            let library = library.as_ref().map(|name| open(name, args.endian));
+            segment_deps(&codefile, library.as_ref())
+        }
     }
 }
 
-fn list(file_name: String) {
-    println!("Listing code file {file_name}");
-    let contents = std::fs::read(file_name).expect("Unable to read file");
-    let segment_dictionary = SegmentDictionary::new(&contents);
-    println!("File length: {}", contents.len());
-    let copyright = match String::from_utf8(segment_dictionary.copyright_string.to_vec()) {
-        Ok(v) => v,
-        Err(e) => panic!("{}", e),
+// Every command starts here, so a file that can't be read stops with a
+// message rather than a panic.
+fn open(name: &str, order: ByteOrder) -> Codefile {
+    return match Codefile::new(name, order) {
+        Ok(codefile) => codefile,
+        Err(message) => {
+            eprintln!("{message}");
+            std::process::exit(1);
+        }
     };
-    println!("Copyright: {}", copyright);
+}
+
+// With kinds, only segments of one of those kinds are listed.
+fn list(codefile: &Codefile, kinds: &[KindFilter]) {
+    println!("Listing code file {}", codefile.name());
+    println!("File length: {}", codefile.bytes().len());
+    println!("Copyright: {}", codefile.copyright());
     println!("Segments:");
-    for s in 0..16 {
-        let code_info = segment_dictionary.code_info[s];
-        if code_info.address == 0 {
+    for segment in codefile.segments() {
+        if !kinds.is_empty() && !kinds.iter().any(|k| k.matches(segment.kind)) {
             continue;
         }
-        let seg_name = segment_dictionary.seg_name[s];
-        let seg_kind = segment_dictionary.seg_kind[s];
-        let text_addr = segment_dictionary.text_addr[s];
-        let seg_info = segment_dictionary.seg_info[s];
-
-        println!("Segment {:#x?}, name: {}, address: {:#x?}, length: {:#x?},", s, string_from(&seg_name), code_info.address*512, code_info.length);
-        println!("\t kind: {:?}, text_addr: {:#x?}, seg_info: {:#x?}", seg_kind, text_addr, string_from_segment_info(seg_info));
+        let s = segment.index;
+        println!("Segment {:#x?}, name: {}, address: {:#x?} (block {}), length: {:#x?} bytes ({} blocks),",
+            s, segment.name, segment.start(), segment.address, segment.length, segment.blocks());
+        if let SegmentKind::UnitSegment = segment.kind {
+            println!("\t kind: {:?}, interface text at block {}, seg_info: {:#x?}", segment.kind, segment.text_addr, string_from_segment_info(segment.seg_info));
+        } else {
+            println!("\t kind: {:?}, text_addr: {:#x?}, seg_info: {:#x?}", segment.kind, segment.text_addr, string_from_segment_info(segment.seg_info));
+        }
+        println!("\t needs linking: {}", segment.kind.needs_linking());
+        for line in link_notes(codefile, &segment) {
+            println!("\t {line}");
+        }
+        if segment.is_main() {
+            println!("\t main program: the outer block, run as segment 1");
+        }
+        if codefile.is_external(s) {
+            println!("\t external/overlay: the code is not in this file");
+        }
     }
     println!();
 }
 
-fn disassemble(file_name: String) {
-    println!("Disassembling code file {file_name}");
+// What a segment that isn't linked yet is waiting for, from its linker
+// information. A host segment is the program itself, so it's called out
+// as not runnable, and linker information that can't be read is reported;
+// for other kinds that's left to needs linking.
+fn link_notes(codefile: &Codefile, segment: &Segment) -> Vec<String> {
+    let mut notes = Vec::new();
+    if !segment.kind.needs_linking() {
+        return notes;
+    }
+    let host = segment.kind == SegmentKind::HostSegment;
+    if host {
+        notes.push("unresolved references: the program has to be linked before it can run".to_string());
+    }
+    match codefile.unresolved_references(segment.index) {
+        Ok(references) => {
+            for (kind, label) in [(LinkKind::Unit, "units"), (LinkKind::External, "external routines")] {
+                let names: Vec<&str> = references.iter().filter(|r| r.kind == kind).map(|r| r.name.trim()).collect();
+                if !names.is_empty() {
+                    notes.push(format!("waiting on {label}: {}", names.join(", ")));
+                }
+            }
+        }
+        Err(e) if host => notes.push(format!("can't tell what it's waiting on: {e}")),
+        Err(_) => {}
+    }
+    return notes;
+}
+
+// To a file if the arguments name one. A file that can't be written is
+// reported like a code file that can't be read.
+fn disassemble(codefile: &Codefile, args: &DisassembleArgs) {
+    let result = match &args.output {
+        Some(path) => std::fs::File::create(path).map(std::io::BufWriter::new)
+            .and_then(|mut file| write_disassembly(&mut file, codefile, args).and_then(|_| file.flush())),
+        None => write_disassembly(&mut std::io::stdout().lock(), codefile, args),
+    };
+    if let Err(e) = result {
+        eprintln!("Can't write the disassembly to {}: {e}", args.output.as_deref().unwrap_or("standard output"));
+        std::process::exit(1);
+    }
+    if let Some(path) = &args.output {
+        println!("Wrote the disassembly of {} to {path}", codefile.name());
+    }
+}
+
+fn write_disassembly(out: &mut impl Write, codefile: &Codefile, args: &DisassembleArgs) -> std::io::Result<()> {
+    if args.format == Syntax::Asm {
+        return write_asm(out, codefile, args.segment, args.procedure);
+    }
+    let listing = Listing { offsets: !args.no_offsets, bytes: !args.no_bytes, annotate: args.annotate };
+    writeln!(out, "Disassembling code file {}", codefile.name())?;
+    for segment in codefile.segments() {
+        if args.segment.is_some_and(|s| s != segment.index) {
+            continue;
+        }
+        writeln!(out, "Segment {:#x?}, name: {}", segment.index, segment.name)?;
+        for line in link_notes(codefile, &segment) {
+            writeln!(out, "\t {line}")?;
+        }
+        match codefile.pcode(segment.index) {
+            Ok((code, endian)) => match args.start {
+                Some(start) => {
+                    let end = args.len.map_or(code.len(), |l| start.saturating_add(l));
+                    disassembler::print_range(out, code, endian, &listing, start, end)?
+                }
+                None => disassembler::print_segment(out, code, endian, &listing, args.procedure)?,
+            },
+            Err(e) => writeln!(out, "\t {e}")?,
+        }
+    }
+    return Ok(());
+}
+
+// The same walk, but everything that isn't source is a comment. Segments
+// with no p-code are noted and skipped.
+fn write_asm(out: &mut impl Write, codefile: &Codefile, only: Option<usize>, procedure: Option<usize>) -> std::io::Result<()> {
+    writeln!(out, "; Disassembly of code file {}", codefile.name())?;
+    for segment in codefile.segments() {
+        if only.is_some_and(|s| s != segment.index) || segment.is_empty() {
+            continue;
+        }
+        match codefile.pcode(segment.index) {
+            Ok((code, endian)) => {
+                writeln!(out)?;
+                for line in link_notes(codefile, &segment) {
+                    writeln!(out, "; {line}")?;
+                }
+                writeln!(out, "        .segment {}, {}", segment.index, segment.name.trim())?;
+                disassembler::print_segment_asm(out, code, endian, procedure)?;
+                writeln!(out, "        .endsegment")?;
+            }
+            Err(e) => writeln!(out, "; Segment {}, {}: {e}", segment.index, segment.name.trim())?,
+        }
+    }
+    return Ok(());
+}
+
+// One bar per segment, scaled to the file, and a combined bar at the bottom
+// where each character shows which segment covers it: 'D' for the dictionary,
+// a hex digit for a segment, '*' where they overlap and '.' where nothing does.
+fn segment_map(codefile: &Codefile, width: usize) {
+    println!("Segment map of code file {}", codefile.name());
+    let file_len = codefile.bytes().len();
+    let width = width.max(1);
+    // Every segment gets at least one character, however short it is.
+    let cells = |start: usize, end: usize| {
+        let first = (start * width / file_len).min(width - 1);
+        let last = (end * width).div_ceil(file_len).clamp(first + 1, width);
+        first..last
+    };
+    let mut combined = vec!['.'; width];
+    let mut mark = |range: std::ops::Range<usize>, c: char| {
+        for cell in &mut combined[range] {
+            *cell = if *cell == '.' { c } else { '*' };
+        }
+    };
+    println!("{} bytes, one character is about {} bytes", file_len, file_len.div_ceil(width));
+    let dictionary = cells(0, 512);
+    mark(dictionary.clone(), 'D');
+    let bar = |range: &std::ops::Range<usize>| -> String {
+        return (0..width).map(|i| if range.contains(&i) { '#' } else { '.' }).collect();
+    };
+    println!("   {:28} |{}| {:#06x}..{:#06x}", "dictionary", bar(&dictionary), 0, 512);
+    for segment in codefile.segments() {
+        let s = segment.index;
+        let range = cells(segment.start(), segment.end());
+        mark(range.clone(), char::from_digit(s as u32, 16).unwrap());
+        let label = format!("{} {:?}", segment.name, segment.kind);
+        let past_end = if segment.end() > file_len { " (past the end of the file)" } else { "" };
+        println!("{:x}  {:28} |{}| {:#06x}..{:#06x}{}", s, label, bar(&range), segment.start(), segment.end(), past_end);
+    }
+    println!("   {:28} |{}|", "all", combined.iter().collect::<String>());
+}
+
+fn profile(codefile: &Codefile, only: Option<usize>) {
+    println!("Profiling code file {}", codefile.name());
+    let mut counts = std::collections::BTreeMap::new();
+    for segment in codefile.segments() {
+        if only.is_some_and(|s| s != segment.index) {
+            continue;
+        }
+        match codefile.pcode(segment.index) {
+            Ok((code, endian)) => disassembler::count_opcodes(code, endian, &mut counts),
+            Err(e) => println!("Segment {:#x?}, name: {}: {e}", segment.index, segment.name),
+        }
+    }
+    let total: usize = counts.values().sum();
+    let mut sorted: Vec<(&str, usize)> = counts.into_iter().collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    for (mnemonic, count) in sorted {
+        println!("{:5} {:6} {:5.1}%", mnemonic, count, count as f64 * 100.0 / total as f64);
+    }
+    println!("{total} instruction(s)");
+}
+
+fn verify_codefile(codefile: &Codefile) {
+    println!("Verifying code file {}", codefile.name());
+    let problems = codefile.problems();
+    for problem in &problems {
+        println!("  {problem}");
+    }
+    for segment in codefile.segments() {
+        if codefile.is_external(segment.index) {
+            println!("  segment {}: external/overlay, its code at {:#x} is not in this file", segment.index, segment.start());
+        }
+    }
+    if problems.is_empty() {
+        println!("No problems found");
+    } else {
+        println!("{} problem(s) found", problems.len());
+        std::process::exit(1);
+    }
+}
+
+// The manual calls library_info "undefined format", so this is a hex dump
+// with notes on anything recognisable: a leading word that points at another
+// dictionary block (as a chained library might have), and runs of text.
+fn library_info(codefile: &Codefile) {
+    println!("Library information in code file {}", codefile.name());
+    let intrinsics = codefile.intrinsic_segments();
+    if intrinsics.is_empty() {
+        println!("Intrinsic segments: none");
+    } else {
+        println!("Intrinsic segments: {:?}", intrinsics);
+    }
+    let info = codefile.library_info();
+    if info.iter().all(|b| *b == 0) {
+        println!("Library information is all zeros");
+        return;
+    }
+    for (line, bytes) in info.chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        let chars: String = bytes.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect();
+        println!("{:04x}  {:47}  |{}|", Codefile::LIBRARY_INFO_OFFSET + line * 16, hex.join(" "), chars);
+    }
+    let first = u16::from_le_bytes([info[0], info[1]]) as usize;
+    if first != 0 && codefile.looks_like_dictionary(first) {
+        println!("Word 0 ({first}) points at block {first}, which looks like another segment dictionary");
+    }
+    let mut start = None;
+    for (i, b) in info.iter().chain(std::iter::once(&0)).enumerate() {
+        let printable = b.is_ascii_graphic() || *b == b' ';
+        match (printable, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                if i - s >= 4 {
+                    println!("Text at {:#06x}: \"{}\"", Codefile::LIBRARY_INFO_OFFSET + s, String::from_utf8_lossy(&info[s..i]));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
 }
 
-fn string_from(pascal_string8: &[u8;8]) -> String {
-    let mut result = String::new();
-    for c in pascal_string8 {
-        if *c == 0x20 {
-            break;
+// With stub set, only Pascal goes to stdout, so it can be redirected to a
+// file and compiled; segments without text are reported on stderr.
+fn interface(codefile: &Codefile, only: Option<usize>, stub: bool) {
+    if stub {
+        return export_interface(codefile, only);
+    }
+    println!("Interface text in code file {}", codefile.name());
+    let segments = match only {
+        Some(s) => s..s + 1,
+        None => 0..16,
+    };
+    let mut found = 0;
+    for s in segments {
+        let segment = codefile.segment_at(s);
+        if segment.kind != SegmentKind::UnitSegment {
+            if only.is_some() {
+                println!("Segment {:#x?}, name: {}, is not a unit", s, segment.name);
+            }
+            continue;
         }
-        result.push(*c as char);
+        found += 1;
+        println!("Segment {:#x?}, name: {}, interface text at block {}", s, segment.name, segment.text_addr);
+        match codefile.interface_text(s) {
+            Ok(text) => print!("{text}"),
+            Err(e) => {
+                println!("\t {e}");
+                // Show what's there instead, in case it's a format this doesn't know.
+                if let Ok(bytes) = codefile.interface_bytes(s) {
+                    println!("\t Warning: showing the raw bytes");
+                    let base = segment.text_addr as usize * 512;
+                    for (line, chunk) in bytes.chunks(hexdump::LINE_LEN).enumerate() {
+                        println!("{}", hexdump::line(base + line * hexdump::LINE_LEN, chunk, hexdump::hex, hexdump::ascii));
+                    }
+                }
+            }
+        }
+    }
+    if found == 0 && only.is_none() {
+        println!("No unit segments");
     }
-    return result;
 }
 
-fn string_from_segment_info(segment_info: u16) -> String {
-    let unit = segment_info & 0xff;
-    let code_type = (segment_info & 0x0f00) >> 8;
-    let type_s = match code_type {
-        0 => "Unknown",
-        1 => "Pcode Big-endian",
-        2 => "Pcode Little-endian",
-        _ => "Native code"
+fn export_interface(codefile: &Codefile, only: Option<usize>) {
+    let segments = match only {
+        Some(s) => s..s + 1,
+        None => 0..16,
     };
-    let version = (segment_info & 0xe000) >> 13;    
-    let result = format!("[unit: {}, type: {}, version: {}]", unit, type_s, version);
-    return result;
-}
\ No newline at end of file
+    let mut found = 0;
+    for s in segments {
+        let segment = codefile.segment_at(s);
+        if segment.kind != SegmentKind::UnitSegment {
+            if only.is_some() {
+                eprintln!("Segment {:#x?}, name: {}, is not a unit", s, segment.name);
+            }
+            continue;
+        }
+        match codefile.interface_text(s) {
+            Ok(text) => {
+                if found > 0 {
+                    println!();
+                }
+                found += 1;
+                println!("(* Segment {s}, {0}, from {1} *)", segment.name.trim(), codefile.name());
+                print!("{}", unit_stub::interface_stub(&segment.name, &text));
+            }
+            Err(e) => eprintln!("Segment {:#x?}, name: {}: {e}", s, segment.name),
+        }
+    }
+    if found == 0 {
+        eprintln!("No interface text to export");
+        std::process::exit(1);
+    }
+}
+
+// The dictionary keeps one intrinsic_segments mask for the whole file, so
+// every segment relies on the same set. Bits for intrinsic units the file
+// holds itself are satisfied here; the rest have to come from the library,
+// where each intrinsic unit's seg_info records the bit it fills.
+fn segment_deps(codefile: &Codefile, library: Option<&Codefile>) {
+    println!("Segment dependencies of code file {}", codefile.name());
+    let intrinsics = codefile.intrinsic_segments();
+    let segments = codefile.segments();
+    let provided = |number: usize| segments.iter().find(|s| s.is_intrinsic() && s.number() == number);
+    for segment in &segments {
+        let relies: Vec<usize> = intrinsics.iter().copied().filter(|n| *n != segment.number()).collect();
+        println!("Segment {:#x?}, name: {}, kind: {:?}, segment number {}", segment.index, segment.name, segment.kind, segment.number());
+        if segment.is_intrinsic() && intrinsics.contains(&segment.number()) {
+            println!("\t provides intrinsic segment {}", segment.number());
+        }
+        if relies.is_empty() {
+            println!("\t relies on no intrinsic segments");
+        } else {
+            println!("\t relies on intrinsic segments {:?}", relies);
+        }
+    }
+    let needed: Vec<usize> = intrinsics.iter().copied().filter(|n| provided(*n).is_none()).collect();
+    if needed.is_empty() {
+        println!("Needs nothing from System.Library");
+        return;
+    }
+    println!("Needs from System.Library: {:?}", needed);
+    let Some(library) = library else {
+        return;
+    };
+    let mut missing = 0;
+    for number in needed {
+        let found: Vec<String> = library.segments().into_iter()
+            .filter(|s| s.is_intrinsic() && s.number() == number)
+            .map(|s| format!("{} (segment {:#x?}, {:?})", s.name, s.index, s.kind))
+            .collect();
+        if found.is_empty() {
+            missing += 1;
+            println!("  {number:2}: not in {}", library.name());
+        } else {
+            println!("  {number:2}: {}", found.join(", "));
+        }
+    }
+    if missing > 0 {
+        println!("{missing} intrinsic segment(s) missing from {}", library.name());
+    }
+}
+
+// Offsets can be given in hex, as the disassembler shows them.
+fn parse_offset(value: &str) -> Result<usize, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    return parsed.map_err(|_| format!("{value} is not a number or 0x-prefixed hex number"));
+}
+
+// A dictionary has 16 slots, numbered as list shows them.
+fn parse_segment(value: &str) -> Result<usize, String> {
+    let segment = parse_offset(value)?;
+    if segment >= 16 {
+        return Err(format!("segment {value} is out of range: a code file has segments 0 to 15"));
+    }
+    return Ok(segment);
+}
+
+// Offsets count from the start of the segment, matching the disassembly,
+// unless absolute asks for file offsets.
+fn dump(codefile: &Codefile, s: usize, from: usize, to: Option<usize>, absolute: bool) {
+    let segment = codefile.segment_at(s);
+    println!("Dumping segment {:#x?}, name: {}, of code file {}", s, segment.name, codefile.name());
+    if segment.is_empty() {
+        println!("\t segment is not in use");
+        return;
+    }
+    let code = match codefile.extract(s) {
+        Ok(code) => code,
+        Err(e) => {
+            println!("\t {e}");
+            return;
+        }
+    };
+    let to = to.unwrap_or(code.len()).min(code.len());
+    if from >= to {
+        println!("\t nothing to show: {from:#x}..{to:#x} is outside the segment's {:#x} bytes", code.len());
+        return;
+    }
+    let base = if absolute { segment.start() } else { 0 };
+    for (line, bytes) in code[from..to].chunks(hexdump::LINE_LEN).enumerate() {
+        println!("{}", hexdump::line(base + from + line * hexdump::LINE_LEN, bytes, hexdump::hex, hexdump::ascii));
+    }
+}
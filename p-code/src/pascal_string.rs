@@ -0,0 +1,65 @@
+// Names are kept in fixed-width fields in one of two ways, and the two tools
+// share these readers so they agree on which is which.
+//
+// Disk directories use length-prefixed strings: a length byte, then that
+// many characters, with the rest of the field unused. The volume name field
+// is 8 bytes, so up to 7 characters, and a file name's is 16, up to 15.
+//
+// A code file's segment dictionary gives each segment name 8 bytes of
+// characters, padded on the right with spaces. There's no length byte.
+
+// The length byte is clamped to the field, so a damaged length can't read
+// past the end of the name.
+pub fn pstring_to_string(pstring: &[u8]) -> String {
+    let Some((length, chars)) = pstring.split_first() else {
+        return String::new();
+    };
+    let len = (*length as usize).min(chars.len());
+    return chars[..len].iter().map(|c| *c as char).collect();
+}
+
+// A name longer than the field is cut short.
+pub fn string_to_pstring<const N: usize>(string: &str) -> [u8; N] {
+    let mut result = [0; N];
+    let len = string.len().min(N - 1);
+    result[0] = len as u8;
+    result[1..=len].copy_from_slice(&string.as_bytes()[..len]);
+    return result;
+}
+
+// Only the padding at the end is dropped, so a space inside a name is kept.
+// Unused dictionary slots are zeroes rather than spaces, so trailing NULs go
+// too.
+pub fn padded_to_string(field: &[u8]) -> String {
+    let len = field.iter().rposition(|c| *c != b' ' && *c != 0).map_or(0, |last| last + 1);
+    return field[..len].iter().map(|c| *c as char).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pstring_length_is_clamped() {
+        assert_eq!(pstring_to_string(b"\x05HELLO"), "HELLO");
+        assert_eq!(pstring_to_string(b"\x03HELLO"), "HEL");
+        assert_eq!(pstring_to_string(b"\xffSHORT"), "SHORT");
+        assert_eq!(pstring_to_string(b""), "");
+    }
+
+    #[test]
+    fn long_names_are_cut_to_the_field() {
+        assert_eq!(&string_to_pstring::<8>("BLANKDISK"), b"\x07BLANKDI");
+        assert_eq!(&string_to_pstring::<8>("WORK"), b"\x04WORK\0\0\0");
+        assert_eq!(pstring_to_string(&string_to_pstring::<16>("SYSTEM.PASCAL")), "SYSTEM.PASCAL");
+    }
+
+    #[test]
+    fn padding_is_dropped_from_the_end_only() {
+        assert_eq!(padded_to_string(b"MY SEG  "), "MY SEG");
+        assert_eq!(padded_to_string(b"HELLO\0\0\0"), "HELLO");
+        assert_eq!(padded_to_string(b"A B \0 \0\0"), "A B");
+        assert_eq!(padded_to_string(b"\0\0\0\0\0\0\0\0"), "");
+        assert_eq!(padded_to_string(b"SEGMENT1"), "SEGMENT1");
+    }
+}
@@ -0,0 +1,100 @@
+// A compiled unit keeps its INTERFACE section as text, so code that USES the
+// unit can be compiled against it. That text is enough to rebuild a stub
+// unit: the heading and interface as they were, then an IMPLEMENTATION that
+// gives every procedure and function an empty body. The stub compiles, but
+// programs compiled against it still need the real unit to link and run.
+//
+// This is a scanner, not a parser. It only finds keywords outside comments
+// and strings, which is enough because UCSD Pascal has no procedure types:
+// every PROCEDURE or FUNCTION in an interface is a routine heading.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Routine {
+    Procedure,
+    Function,
+}
+
+// An identifier or keyword, upper-cased, with where it sits in the text.
+struct Word {
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+// Comments are { } or (* *); strings are in single quotes, with '' for a
+// quote inside one. Neither holds any words.
+fn words(text: &str) -> Vec<Word> {
+    let bytes = text.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if rest[0] == b'{' {
+            i += rest.iter().position(|b| *b == b'}').map_or(rest.len(), |e| e + 1);
+        } else if rest.starts_with(b"(*") {
+            i += rest.windows(2).position(|w| w == b"*)").map_or(rest.len(), |e| e + 2);
+        } else if rest[0] == b'\'' {
+            i += rest[1..].iter().position(|b| *b == b'\'').map_or(rest.len(), |e| e + 2);
+        } else if rest[0].is_ascii_alphabetic() {
+            let len = rest.iter().position(|b| !b.is_ascii_alphanumeric() && *b != b'_').unwrap_or(rest.len());
+            result.push(Word { text: text[i..i + len].to_ascii_uppercase(), start: i, end: i + len });
+            i += len;
+        } else {
+            i += 1;
+        }
+    }
+    return result;
+}
+
+// Trailing blanks are dropped from every line, and blank lines from the end.
+fn tidy(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    let end = lines.iter().rposition(|l| !l.is_empty()).map_or(0, |i| i + 1);
+    return lines[..end].join("\n");
+}
+
+// Rebuild a unit from its interface text. The compiler stores the text from
+// the UNIT heading to the IMPLEMENTATION keyword, but a heading that's
+// missing is made up from unit_name, and anything from IMPLEMENTATION on is
+// dropped.
+pub fn interface_stub(unit_name: &str, text: &str) -> String {
+    let words = words(text);
+    let interface = words.iter().position(|w| w.text == "INTERFACE");
+    let implementation = words.iter().position(|w| w.text == "IMPLEMENTATION");
+    let body_start = interface.map_or(0, |i| words[i].end);
+    let body_end = implementation.map_or(text.len(), |i| words[i].start);
+    let heading = match interface {
+        Some(i) if words[..i].iter().any(|w| w.text == "UNIT") => tidy(&text[..words[i].start]),
+        _ => format!("UNIT {};", unit_name.trim()),
+    };
+    let mut routines = Vec::new();
+    for (n, word) in words.iter().enumerate() {
+        if word.start < body_start || word.end > body_end {
+            continue;
+        }
+        let kind = match word.text.as_str() {
+            "PROCEDURE" => Routine::Procedure,
+            "FUNCTION" => Routine::Function,
+            _ => continue,
+        };
+        if let Some(name) = words.get(n + 1).filter(|w| w.end <= body_end) {
+            routines.push((kind, &text[name.start..name.end]));
+        }
+    }
+    let mut stub = format!("{heading}\nINTERFACE\n");
+    let body = tidy(&text[body_start.min(body_end)..body_end]);
+    let body = body.trim_start_matches('\n');
+    if !body.is_empty() {
+        stub.push_str(body);
+        stub.push('\n');
+    }
+    stub.push_str("\nIMPLEMENTATION\n");
+    // The parameters and result type were given in the interface, so the
+    // bodies repeat only the names.
+    for (kind, name) in routines {
+        let keyword = if kind == Routine::Procedure { "PROCEDURE" } else { "FUNCTION" };
+        stub.push_str(&format!("\n{keyword} {name};\nBEGIN\nEND;\n"));
+    }
+    stub.push_str("\nEND.\n");
+    return stub;
+}
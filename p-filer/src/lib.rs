@@ -0,0 +1,2 @@
+#![allow(clippy::needless_return)]
+pub mod p_system_fs;
@@ -1,53 +1,424 @@
-use std::usize;
+#![allow(clippy::needless_return)]
 use clap::{Args, Parser, Subcommand};
-mod p_system_fs;
-use p_system_fs::AppleDisk;
+use p_filer::p_system_fs;
+use p_system_fs::{follow, parse_page_size, stats, watch, AppleDisk, DiskError, DiskOptions, Encoding, ErrorKind, FileKind, FileOrder, Geometry, SectorOrder, TransferOptions, TEXT_PAGE_SIZE};
 
 /// A command-file tool for manipulating Apple Pascal disk images
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct MainArgs {
-    /// Name of disk image to use
+    /// Name of disk image to use; every command but stats needs one
     #[arg(short, long)]
-    image: String,
+    image: Option<String>,
+    /// Block within the image where the Pascal volume starts
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+    /// ProDOS file holding the Pascal volume, e.g. /PASCAL.VOL on a .hdv
+    #[arg(long)]
+    prodos_path: Option<String>,
+    /// Sector order of the image: "none", "dos", or a map with one entry
+    /// per sector of a track, e.g. "0,14,13,12,11,10,9,8,7,6,5,4,3,2,1,15"
+    /// for 16 sectors
+    #[arg(long, value_parser = SectorOrder::parse)]
+    interleave: Option<SectorOrder>,
+    /// Number of tracks in the image, checked against its length; worked
+    /// out from the length if not given
+    #[arg(long)]
+    tracks: Option<usize>,
+    /// Sectors in each track, for media other than 16-sector Apple II
+    /// floppies; an interleave map needs this many entries. A .d13 image is
+    /// taken to have 13 unless a different geometry is given
+    #[arg(long, default_value_t = 16, value_parser = parse_nonzero)]
+    sectors_per_track: usize,
+    /// Bytes in each sector
+    #[arg(long, default_value_t = 256, value_parser = parse_nonzero)]
+    sector_size: usize,
+    /// Log where each sector of the image is read from, on stderr, to find
+    /// out why an image with the wrong sector order won't read
+    #[arg(long)]
+    trace_interleave: bool,
+    /// Size of a textfile page, and of its header, in bytes. Apple Pascal
+    /// uses 1024; other p-systems may differ
+    #[arg(long, global = true, default_value_t = TEXT_PAGE_SIZE, value_parser = parse_page_size)]
+    text_page_size: usize,
+    /// Show what a command would change without writing the image
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Show each date's stored two-digit year and packed word as well, since
+    /// the century is a guess
+    #[arg(long, global = true)]
+    raw_dates: bool,
+    /// Don't copy the image to IMAGE.bak before changing it
+    #[arg(long, global = true)]
+    no_backup: bool,
+    /// Don't colour listings and dumps, even on a terminal
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Refuse to work on an image that verify finds any problem with
+    #[arg(long, global = true)]
+    strict: bool,
+    /// Report errors on stderr as a JSON object
+    #[arg(long, global = true)]
+    json: bool,
     #[command(subcommand)]
     command: Commands
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    List,
-    Remove {name: String},
+    List(ListArgs),
+    Remove {
+        #[arg(required_unless_present = "index")]
+        name: Option<String>,
+        /// Remove the file in this directory slot instead, whatever its name
+        #[arg(long, conflicts_with = "name")]
+        index: Option<usize>,
+        /// Also overwrite the file's blocks with zeros, so it can't be undeleted
+        #[arg(long)]
+        zero_fill: bool,
+    },
+    /// List the files grouped by type, with the blocks each group uses
+    Tree,
+    /// Print a CRC-32 of the boot blocks, to recognise the release a boot
+    /// disk came from
+    BootSignature {
+        /// Also print one of SYSTEM.PASCAL
+        #[arg(long)]
+        system: bool,
+    },
+    /// Show an infofile, the debugger's information for a codefile, with the
+    /// segments of the codefile it goes with
+    InfoFile {name: String},
+    /// Print the byte offset and length of a file in the de-interleaved image
+    Locate {name: String},
     Transfer(TransferArgs),
     Change {from: String, to: String},
-    Krunch,
-    Zero,
-    Dump {from: usize, to: usize} 
+    /// Change the type a file is marked as, e.g. to fix an untyped textfile
+    SetType {
+        name: String,
+        #[arg(value_enum)]
+        kind: FileKind,
+    },
+    /// Copy every file into a directory, with a manifest.json of their names
+    ExtractAll {
+        /// Directory to write the files into
+        #[arg(default_value = ".")]
+        dir: String,
+        /// Decode text files to plain text
+        #[arg(long)]
+        text: bool,
+    },
+    /// Decode every textfile into one document, each headed by its name
+    ExportText {
+        /// File to write the document to
+        #[arg(long)]
+        combined: String,
+        /// Order of the files in the document
+        #[arg(long, value_enum, default_value_t = FileOrder::Name)]
+        order: FileOrder,
+        /// Overwrite it if it exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Write the Pascal volume to an image of its own, e.g. one found with
+    /// --prodos-path on a hard disk image
+    ExtractVolume {
+        /// Image to write; its name picks the sector order, .po or .dsk
+        out: String,
+        /// Overwrite it if it exists
+        #[arg(long)]
+        force: bool,
+    },
+    Krunch {
+        /// Don't print the before and after layout
+        #[arg(long)]
+        quiet: bool,
+    },
+    Zero {
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Replace the directory with one listing the code and text files found
+    /// by scanning the volume's blocks
+    RebuildDirectory {
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Check the directory for damage
+    Verify {
+        /// Remove entries whose blocks can't be used or overlap another
+        /// file's, and cut short those that run past the end of the volume
+        #[arg(long)]
+        fix: bool,
+        /// Don't ask for confirmation
+        #[arg(long, short, requires = "fix")]
+        yes: bool,
+    },
+    /// Fix damage to the directory
+    Repair {
+        /// Reset a wrong first block after directory to 6
+        #[arg(long)]
+        fix_dir_start: bool,
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// List the image again each time another program changes it
+    Watch {
+        /// Seconds between checks
+        #[arg(long, default_value_t = 1.0)]
+        interval: f64,
+    },
+    /// Summarise every .dsk, .do, .d13 and .po image in a directory tree, gzipped
+    /// or not, and every .zip holding one
+    Stats {
+        #[arg(long)]
+        dir: String,
+    },
+    /// Search every text file for a string
+    FindText {
+        pattern: String,
+        /// Print only the number of matching lines; none sets exit status 3
+        #[arg(long)]
+        count_only: bool,
+    },
+    /// Write raw bytes into a block
+    Patch {
+        #[arg(long)]
+        block: usize,
+        /// Byte offset within the block
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+        /// Hex bytes to write, e.g. "00 06 00"
+        #[arg(long)]
+        bytes: String,
+        /// Really write them
+        #[arg(long)]
+        force: bool,
+    },
+    /// Copy raw blocks from another image's volume into this one, e.g. to
+    /// put back boot blocks or salvage a region from a good copy
+    CopyBlocks {
+        /// Image to copy from; --offset, --prodos-path and --interleave
+        /// only apply to --image
+        source: String,
+        /// First block to copy from the source
+        #[arg(long)]
+        src_from: usize,
+        /// Number of blocks to copy
+        #[arg(long)]
+        count: usize,
+        /// Block to copy them to in this image
+        #[arg(long)]
+        dst_at: usize,
+        /// Really write them
+        #[arg(long)]
+        force: bool,
+    },
+    Dump {
+        from: usize,
+        #[arg(required_unless_present = "follow")]
+        to: Option<usize>,
+        /// Finish with a count of zero and non-zero bytes
+        #[arg(long)]
+        count: bool,
+        /// How to show bytes as characters
+        #[arg(long, value_enum, default_value_t = Encoding::Ascii)]
+        encoding: Encoding,
+        /// Show high-ASCII bytes as the characters they are with the high
+        /// bit cleared; the same as --encoding apple
+        #[arg(long, conflicts_with = "encoding")]
+        high_ascii: bool,
+        /// Keep watching the image and dump blocks as they're added
+        #[arg(long, conflicts_with_all = ["to", "count"])]
+        follow: bool,
+        /// Seconds between checks when following
+        #[arg(long, default_value_t = 1.0, requires = "follow")]
+        interval: f64,
+    }
+}
+
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Skip this many entries before listing
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+    /// List at most this many entries
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Only list files of this type
+    #[arg(long = "type", value_enum)]
+    file_type: Option<FileKind>,
+    /// One line per file
+    #[arg(long, short)]
+    short: bool,
+    /// Show a checksum of each file's blocks, to spot copies that differ
+    #[arg(long)]
+    block_checksum: bool,
+    /// Print only the number of files that would be listed; none sets exit
+    /// status 3
+    #[arg(long, conflicts_with_all = ["short", "block_checksum"])]
+    count_only: bool,
+    /// Also show the volume header's raw fields: the first block, file type,
+    /// last access time and reserved bytes
+    #[arg(long, short, conflicts_with_all = ["short", "count_only"])]
+    verbose: bool,
 }
 
 #[derive(Args, Debug)]
 struct TransferArgs {
-    name: String,
+    #[arg(required_unless_present_any = ["index", "from_list"])]
+    name: Option<String>,
+    /// Copy the file in this directory slot instead, whatever its name
+    #[arg(long, conflicts_with_all = ["name", "to_image"])]
+    index: Option<usize>,
+    /// Copy every file named in this list, one name or entry number a line,
+    /// going on past any that can't be copied
+    #[arg(long, conflicts_with_all = ["name", "index", "to_image", "at_block"])]
+    from_list: Option<String>,
     #[arg(long)]
     to_image: bool,
     #[arg(long)]
     text: bool,
     #[arg(long, short)]
     preserve_date: bool,
+    /// Keep the CR line endings of text files instead of converting to LF
+    #[arg(long, requires = "text")]
+    keep_cr: bool,
+    /// Overwrite a host file that already has the same name
+    #[arg(long)]
+    force: bool,
+    /// Copy a text file in its on-disk format, keeping the header page
+    #[arg(long, conflicts_with = "text")]
+    as_blocks: bool,
+    /// Put the file at this block on the image, instead of the first free space
+    #[arg(long, requires = "to_image")]
+    at_block: Option<usize>,
+    /// Copy the INTERFACE text kept in a unit's codefile, to NAME with .TEXT
+    /// in place of .CODE
+    #[arg(long, conflicts_with_all = ["to_image", "text", "as_blocks"])]
+    interface: bool,
+    /// Convert a fotofile's hi-res screen to a PNG, named NAME with .png in
+    /// place of .FOTO
+    #[arg(long, conflicts_with_all = ["to_image", "text", "as_blocks", "interface"])]
+    convert_foto: bool,
 }
 
+// Errors go to stderr, as text or JSON, and set the exit status from their
+// kind.
 fn main() {
     let args = MainArgs::parse();
-    let image = args.image;
-    let d = AppleDisk::new(&image);
-    match &args.command {
-        Commands::List => d.list(),
-        Commands::Remove { name } => d.remove(name),
-        Commands::Transfer(args, ) => d.transfer(&args.name, args.to_image, args.text, args.preserve_date),
-        Commands::Change { from, to } => d.change(from, to),
-        Commands::Krunch => d.krunch(),
-        Commands::Zero => d.zero(),
-        Commands::Dump { from, to } => d.dump(*from, *to)
+    let json = args.json;
+    if let Err(error) = run(args) {
+        if json {
+            eprintln!("{}", error.to_json());
+        } else {
+            eprintln!("Error: {error}");
+        }
+        std::process::exit(error.kind.exit_code());
     }
 }
+
+fn run(args: MainArgs) -> Result<(), DiskError> {
+    let options = DiskOptions {
+        offset: args.offset,
+        prodos_path: args.prodos_path.clone(),
+        interleave: args.interleave.clone(),
+        geometry: Geometry { tracks: args.tracks, sectors: args.sectors_per_track, sector_size: args.sector_size },
+        text_page_size: args.text_page_size,
+        color: p_system_fs::color::enabled(args.no_color),
+        raw_dates: args.raw_dates,
+        quiet: is_count_only(&args.command),
+        backup: !args.no_backup,
+        trace_interleave: args.trace_interleave,
+    };
+    if let Commands::Stats { dir } = &args.command {
+        return stats(dir, &options);
+    }
+    let Some(image) = args.image else {
+        return Err(DiskError::new(ErrorKind::Usage, "No image given: use --image"));
+    };
+    if let Commands::Watch { interval } = &args.command {
+        return watch(&image, &options, *interval);
+    }
+    if let Commands::Dump { from, encoding, high_ascii, follow: true, interval, .. } = &args.command {
+        return follow(&image, &options, *from, dump_encoding(*encoding, *high_ascii), *interval);
+    }
+    let mut d = AppleDisk::new(&image, &options)?;
+    if args.strict && !matches!(args.command, Commands::Verify { .. }) {
+        d.check_strict()?;
+    }
+    let dry_run = args.dry_run;
+    return match &args.command {
+        Commands::List(args) if args.count_only => d.count_files(args.offset, args.limit, args.file_type),
+        Commands::List(args) => d.list(args.offset, args.limit, args.file_type, args.short, args.block_checksum, args.verbose),
+        Commands::Remove { index: Some(index), zero_fill, .. } => d.remove_index(*index, *zero_fill, dry_run),
+        Commands::Remove { name, zero_fill, .. } => d.remove(name.as_deref().unwrap_or_default(), *zero_fill, dry_run),
+        Commands::Tree => d.tree(),
+        Commands::InfoFile { name } => d.info_file(name),
+        Commands::BootSignature { system } => d.boot_signature(*system),
+        Commands::Locate { name } => d.locate(name),
+        Commands::Transfer(args, ) => {
+            let options = TransferOptions {
+                is_text: args.text,
+                preserve_date: args.preserve_date,
+                keep_cr: args.keep_cr,
+                force: args.force,
+                as_blocks: args.as_blocks,
+                at_block: args.at_block,
+                interface: args.interface,
+                convert_foto: args.convert_foto,
+            };
+            match (args.index, &args.from_list) {
+                (_, Some(list)) => d.transfer_list(list, &options),
+                (Some(index), None) => d.transfer_index(index, &options),
+                (None, None) => d.transfer(args.name.as_deref().unwrap_or_default(), args.to_image, &options, dry_run),
+            }
+        }
+        Commands::Change { from, to } => d.change(from, to, dry_run),
+        Commands::SetType { name, kind } => d.set_type(name, *kind, dry_run),
+        Commands::ExtractAll { dir, text } => d.extract_all(dir, *text),
+        Commands::ExportText { combined, order, force } => d.export_text(combined, *order, *force),
+        Commands::ExtractVolume { out, force } => d.extract_volume(out, *force),
+        Commands::Krunch { quiet } => d.krunch(*quiet, dry_run),
+        Commands::Zero { yes } => d.zero(*yes, dry_run),
+        Commands::Verify { fix: true, yes } => d.fix_entries(*yes, dry_run),
+        Commands::Verify { .. } => d.verify(),
+        Commands::RebuildDirectory { yes } => d.rebuild_directory(*yes, dry_run),
+        Commands::Repair { fix_dir_start: true, yes } => d.fix_directory_start(*yes, dry_run),
+        Commands::Repair { .. } => Err(DiskError::new(ErrorKind::Usage, "Nothing to repair: give --fix-dir-start")),
+        Commands::Stats { .. } => unreachable!("stats doesn't open an image"),
+        Commands::Watch { .. } => unreachable!("watch opens the image itself"),
+        Commands::FindText { pattern, count_only } => d.find_text(pattern, *count_only),
+        Commands::Patch { block, offset, bytes, force } => d.patch(*block, *offset, bytes, *force, dry_run),
+        Commands::CopyBlocks { source, src_from, count, dst_at, force } => {
+            let source = AppleDisk::new(source, &DiskOptions { offset: 0, prodos_path: None, interleave: None, geometry: Geometry::default(), ..options })?;
+            d.copy_blocks(&source, *src_from, *count, *dst_at, *force, dry_run)
+        }
+        Commands::Dump { from, to, count, encoding, high_ascii, .. } =>
+            d.dump(*from, to.unwrap_or(*from), *count, dump_encoding(*encoding, *high_ascii))
+    };
+}
+
+fn is_count_only(command: &Commands) -> bool {
+    return match command {
+        Commands::List(args) => args.count_only,
+        Commands::FindText { count_only, .. } => *count_only,
+        _ => false,
+    };
+}
+
+fn dump_encoding(encoding: Encoding, high_ascii: bool) -> Encoding {
+    return if high_ascii { Encoding::Apple } else { encoding };
+}
+
+fn parse_nonzero(value: &str) -> Result<usize, String> {
+    return match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("{value} is not a number")),
+    };
+}
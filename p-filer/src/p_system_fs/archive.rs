@@ -0,0 +1,519 @@
+// Images kept compressed, as .gz files or members of a .zip, are unpacked in
+// memory before they're read. Both hold DEFLATE data, so there's an inflater
+// here rather than a dependency; it only has to be correct, not fast, for
+// files the size of a disk.
+//
+// A zip can hold more than one image, so a member is picked with
+// archive.zip#MEMBER. Without one, a zip must hold a single image.
+//
+// Writing back is only done for gzip. The data is stored rather than
+// compressed, which any gunzip reads; zips aren't rewritten.
+
+use super::{DiskError, ErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Container {
+    Plain,
+    Gzip,
+    Zip,
+}
+
+pub struct Unpacked {
+    pub contents: Vec<u8>,
+    pub container: Container,
+    pub inner_name: String, // the image's own name, to pick its sector order
+}
+
+// The file to open for an image name, and the zip member it names, if any.
+pub fn split_member(name: &str) -> (&str, Option<&str>) {
+    if let Some(at) = name.as_bytes().windows(5).position(|w| w.eq_ignore_ascii_case(b".zip#")) {
+        return (&name[..at + 4], Some(&name[at + 5..]));
+    }
+    return (name, None);
+}
+
+// A damaged archive is a CorruptImage; a member that wasn't picked, or
+// can't be, is the caller's to fix.
+pub fn unpack(name: &str, bytes: Vec<u8>) -> Result<Unpacked, DiskError> {
+    let (path, member) = split_member(name);
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let inner_name = path.strip_suffix(".gz").or_else(|| path.strip_suffix(".GZ")).unwrap_or(path);
+        let contents = gunzip(&bytes).map_err(|e| DiskError::new(ErrorKind::CorruptImage, e))?;
+        return Ok(Unpacked { contents, container: Container::Gzip, inner_name: inner_name.to_string() });
+    }
+    if bytes.starts_with(b"PK\x03\x04") {
+        let (inner_name, contents) = unzip(&bytes, member)?;
+        return Ok(Unpacked { contents, container: Container::Zip, inner_name });
+    }
+    if member.is_some() {
+        return Err(DiskError::new(ErrorKind::Usage, format!("{path} is not a zip file, so it has no members")));
+    }
+    return Ok(Unpacked { contents: bytes, container: Container::Plain, inner_name: name.to_string() });
+}
+
+fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "the gzip file is truncated".to_string();
+    if bytes.len() < 18 || bytes[2] != 8 {
+        return Err("not a gzip file this can read: only DEFLATE is supported".to_string());
+    }
+    let flags = bytes[3];
+    let mut pos = 10;
+    if flags & 0x04 != 0 {
+        pos += 2 + (*bytes.get(pos).ok_or_else(truncated)? as usize | (*bytes.get(pos + 1).ok_or_else(truncated)? as usize) << 8);
+    }
+    // Original name and comment, each zero-terminated.
+    for flag in [0x08, 0x10] {
+        if flags & flag != 0 {
+            pos += bytes.get(pos..).and_then(|rest| rest.iter().position(|b| *b == 0)).ok_or_else(truncated)? + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        pos += 2;
+    }
+    // The trailer ends the file with the unpacked size, which bounds how much
+    // the data may unpack to.
+    let size = u32::from_le_bytes([bytes[bytes.len() - 4], bytes[bytes.len() - 3], bytes[bytes.len() - 2], bytes[bytes.len() - 1]]);
+    let (contents, used) = inflate(bytes.get(pos..).ok_or_else(truncated)?, size as usize)?;
+    let trailer = bytes.get(pos + used..pos + used + 8).ok_or_else(truncated)?;
+    if u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != crc32(&contents) {
+        return Err("the gzip file's CRC doesn't match its contents".to_string());
+    }
+    return Ok(contents);
+}
+
+pub fn gzip(contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend(deflate_stored(contents));
+    out.extend(crc32(contents).to_le_bytes());
+    out.extend((contents.len() as u32).to_le_bytes());
+    return out;
+}
+
+struct ZipMember {
+    name: String,
+    method: usize,
+    size: usize,
+    crc: u32,
+    header: usize, // offset of its local header
+}
+
+fn word(bytes: &[u8], offset: usize) -> usize {
+    return bytes[offset] as usize | (bytes[offset + 1] as usize) << 8;
+}
+
+fn long(bytes: &[u8], offset: usize) -> usize {
+    return word(bytes, offset) | word(bytes, offset + 2) << 16;
+}
+
+// The central directory at the end of the file lists the members.
+fn zip_members(bytes: &[u8]) -> Result<Vec<ZipMember>, String> {
+    let end = (0..bytes.len().saturating_sub(21)).rev()
+        .find(|i| bytes[*i..].starts_with(b"PK\x05\x06"))
+        .ok_or("the zip file has no central directory")?;
+    let count = word(bytes, end + 10);
+    let mut pos = long(bytes, end + 16);
+    let mut members = Vec::new();
+    for _ in 0..count {
+        if pos + 46 > bytes.len() || !bytes[pos..].starts_with(b"PK\x01\x02") {
+            return Err("the zip file's central directory is damaged".to_string());
+        }
+        let name_length = word(bytes, pos + 28);
+        let name = bytes.get(pos + 46..pos + 46 + name_length).ok_or("the zip file's central directory is damaged")?;
+        members.push(ZipMember {
+            name: String::from_utf8_lossy(name).to_string(),
+            method: word(bytes, pos + 10),
+            crc: long(bytes, pos + 16) as u32,
+            size: long(bytes, pos + 24),
+            header: long(bytes, pos + 42),
+        });
+        pos += 46 + name_length + word(bytes, pos + 30) + word(bytes, pos + 32);
+    }
+    return Ok(members);
+}
+
+fn unzip(bytes: &[u8], wanted: Option<&str>) -> Result<(String, Vec<u8>), DiskError> {
+    let members = zip_members(bytes).map_err(|e| DiskError::new(ErrorKind::CorruptImage, e))?;
+    let files: Vec<&ZipMember> = members.iter().filter(|m| !m.name.ends_with('/')).collect();
+    let names = || files.iter().map(|m| m.name.as_str()).collect::<Vec<_>>().join(", ");
+    let member = match wanted {
+        Some(name) => files.iter().find(|m| m.name == name)
+            .or_else(|| files.iter().find(|m| m.name.eq_ignore_ascii_case(name)))
+            .ok_or_else(|| DiskError::new(ErrorKind::NotFound, format!("the zip file has no member {name}; it has {0}", names())))?,
+        None => match files.as_slice() {
+            [only] => only,
+            _ => return Err(DiskError::new(ErrorKind::Usage, format!(
+                "the zip file has {0} members, so pick one with archive.zip#MEMBER: {1}", files.len(), names()))),
+        },
+    };
+    return unzip_member(bytes, member).map_err(|e| DiskError::new(ErrorKind::CorruptImage, e));
+}
+
+fn unzip_member(bytes: &[u8], member: &ZipMember) -> Result<(String, Vec<u8>), String> {
+    let header = member.header;
+    if header + 30 > bytes.len() || !bytes[header..].starts_with(b"PK\x03\x04") {
+        return Err(format!("{0}: the zip file's local header is damaged", member.name));
+    }
+    let data = header + 30 + word(bytes, header + 26) + word(bytes, header + 28);
+    let contents = match member.method {
+        0 => bytes.get(data..data + member.size).ok_or("the zip file is truncated")?.to_vec(),
+        8 => inflate(bytes.get(data..).ok_or("the zip file is truncated")?, member.size)?.0,
+        method => return Err(format!("{0}: zip compression method {method} isn't supported, only stored and DEFLATE", member.name)),
+    };
+    if contents.len() != member.size || crc32(&contents) != member.crc {
+        return Err(format!("{0}: the unpacked size or CRC doesn't match the zip file's", member.name));
+    }
+    return Ok((member.name.clone(), contents));
+}
+
+// DEFLATE data as stored blocks, with no compression.
+pub fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+    for (n, block) in blocks.iter().enumerate() {
+        out.push(if n + 1 == blocks.len() { 1 } else { 0 });
+        let length = block.len() as u16;
+        out.extend(length.to_le_bytes());
+        out.extend((!length).to_le_bytes());
+        out.extend(*block);
+    }
+    if data.is_empty() {
+        out.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    return out;
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    return !crc;
+}
+
+// Bits are read from each byte lowest first.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or("the compressed data is truncated")?;
+            self.buffer |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        return Ok(value);
+    }
+}
+
+// A canonical Huffman code, as the number of codes of each length and the
+// symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::new();
+        for length in 1..16 {
+            for (symbol, l) in lengths.iter().enumerate() {
+                if *l as usize == length {
+                    symbols.push(symbol as u16);
+                }
+            }
+        }
+        return Self { counts, symbols };
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        return Err("the compressed data has an invalid code".to_string());
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073,
+    4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// The order code length code lengths are sent in.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Inflate raw DEFLATE data, returning it and how many bytes it took up. Data
+// that would unpack to more than limit bytes is refused, rather than filling
+// memory.
+fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut bits = Bits { data, pos: 0, buffer: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                // Stored blocks start on a byte boundary.
+                bits.buffer = 0;
+                bits.count = 0;
+                let header = data.get(bits.pos..bits.pos + 4).ok_or("the compressed data is truncated")?;
+                let length = header[0] as usize | (header[1] as usize) << 8;
+                if header[2] != !header[0] || header[3] != !header[1] {
+                    return Err("the compressed data has a stored block whose length check doesn't match".to_string());
+                }
+                if out.len() + length > limit {
+                    return Err(too_long(limit));
+                }
+                bits.pos += 4;
+                out.extend(data.get(bits.pos..bits.pos + length).ok_or("the compressed data is truncated")?);
+                bits.pos += length;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                inflate_block(&mut bits, &mut out, limit, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, limit, &literals, &distances)?;
+            }
+            _ => return Err("the compressed data has an invalid block type".to_string()),
+        }
+        if last {
+            return Ok((out, bits.pos));
+        }
+    }
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_length_count = bits.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[*index] = bits.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+    let mut lengths = Vec::new();
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or("the compressed data repeats a code length before the first")?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("the compressed data's code lengths run over".to_string());
+    }
+    return Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])));
+}
+
+fn too_long(limit: usize) -> String {
+    return format!("the compressed data unpacks to more than the {limit} bytes the archive says it holds");
+}
+
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, limit: usize, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        if symbol != 256 && out.len() >= limit {
+            return Err(too_long(limit));
+        }
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err("the compressed data has an invalid length".to_string());
+        }
+        let length = LENGTH_BASE[index] as usize + bits.bits(LENGTH_EXTRA[index] as u32)? as usize;
+        let index = distances.decode(bits)? as usize;
+        if index >= DISTANCE_BASE.len() {
+            return Err("the compressed data has an invalid distance".to_string());
+        }
+        let distance = DISTANCE_BASE[index] as usize + bits.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+        if distance > out.len() {
+            return Err("the compressed data refers back past its start".to_string());
+        }
+        if out.len() + length > limit {
+            return Err(too_long(limit));
+        }
+        // The copy can overlap what it's writing, so it goes a byte at a time.
+        let start = out.len() - distance;
+        for i in 0..length {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM_TEXT: &[u8] = b"PROGRAM FILER;\nVAR I, J: INTEGER;\nBEGIN\n  FOR I := 1 TO 10 DO\n    FOR J := 1 TO 10 DO\n      WRITE(I * J: 4);\n  WRITELN;\n  WRITELN('THAT IS A TIMES TABLE')\nEND.\n";
+
+    // "HELLO\n" in a stored block.
+    const GZIP_STORED: [u8; 29] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x01, 0x06, 0x00, 0xf9, 0xff, 0x48,
+        0x45, 0x4c, 0x4c, 0x4f, 0x0a, 0x6e, 0xd7, 0xac, 0xfd, 0x06, 0x00, 0x00, 0x00,
+    ];
+
+    // "HELLO, HELLO, HELLO WORLD\n" with the fixed codes, one back-reference.
+    const GZIP_FIXED: [u8; 36] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xf3, 0x70, 0xf5, 0xf1, 0xf1, 0xd7,
+        0x51, 0xf0, 0x40, 0xa2, 0x14, 0xc2, 0xfd, 0x83, 0x7c, 0x5c, 0xb8, 0x00, 0xb2, 0x2a, 0x6b, 0xcb,
+        0x1a, 0x00, 0x00, 0x00,
+    ];
+
+    // PROGRAM_TEXT with codes of its own.
+    const GZIP_DYNAMIC: [u8; 130] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x6d, 0xcc, 0xbb, 0x0a, 0x83, 0x40,
+        0x10, 0x85, 0xe1, 0x7e, 0x9e, 0xe2, 0x74, 0x5e, 0x08, 0x12, 0x21, 0x95, 0x92, 0x62, 0xc4, 0x71,
+        0x33, 0xb2, 0xee, 0xca, 0x38, 0x98, 0xf7, 0x7f, 0x93, 0x28, 0xa6, 0x48, 0x91, 0xee, 0xf0, 0x1d,
+        0xf8, 0x57, 0xcb, 0xc1, 0x78, 0xc1, 0xa4, 0x51, 0xac, 0xa7, 0x9d, 0x0d, 0x7a, 0xc3, 0xdc, 0x41,
+        0x93, 0x4b, 0x38, 0x69, 0x90, 0xa0, 0x89, 0x80, 0x29, 0x1f, 0x17, 0xba, 0x27, 0x5a, 0x78, 0x46,
+        0x7b, 0xc7, 0x98, 0x0f, 0xbd, 0x7c, 0xfe, 0xe3, 0xc0, 0xdb, 0xd4, 0xa5, 0x54, 0xd4, 0x67, 0xef,
+        0x51, 0xf5, 0xf4, 0xa5, 0x98, 0x7e, 0x66, 0x59, 0xf8, 0x8b, 0x1d, 0xba, 0x81, 0xe1, 0xba, 0xc8,
+        0x06, 0xe7, 0x21, 0x4a, 0x51, 0x91, 0xa4, 0xb1, 0xa1, 0x0f, 0x6c, 0x73, 0xc1, 0x10, 0xa0, 0x00,
+        0x00, 0x00,
+    ];
+
+    // ONE.PO holding "FIRST" and TWO.DSK holding "SECOND", both stored.
+    const ZIP_TWO_MEMBERS: [u8; 211] = [
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0xe7, 0x2c,
+        0x25, 0x65, 0x05, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x4f, 0x4e,
+        0x45, 0x2e, 0x50, 0x4f, 0x46, 0x49, 0x52, 0x53, 0x54, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0xef, 0xd6, 0xe7, 0x46, 0x06, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x54, 0x57, 0x4f, 0x2e, 0x44, 0x53, 0x4b, 0x53, 0x45,
+        0x43, 0x4f, 0x4e, 0x44, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x21, 0x00, 0xe7, 0x2c, 0x25, 0x65, 0x05, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+        0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x00, 0x00,
+        0x00, 0x00, 0x4f, 0x4e, 0x45, 0x2e, 0x50, 0x4f, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0xef, 0xd6, 0xe7, 0x46, 0x06, 0x00, 0x00, 0x00,
+        0x06, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x80, 0x01, 0x29, 0x00, 0x00, 0x00, 0x54, 0x57, 0x4f, 0x2e, 0x44, 0x53, 0x4b, 0x50, 0x4b, 0x05,
+        0x06, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0x69, 0x00, 0x00, 0x00, 0x54, 0x00, 0x00,
+        0x00, 0x00, 0x00,
+    ];
+
+
+    fn unpack_error(name: &str, bytes: &[u8]) -> DiskError {
+        return unpack(name, bytes.to_vec()).err().expect("should not unpack");
+    }
+
+    #[test]
+    fn gunzip_reads_each_block_type() {
+        for (bytes, contents) in [(&GZIP_STORED[..], &b"HELLO\n"[..]), (&GZIP_FIXED[..], b"HELLO, HELLO, HELLO WORLD\n"),
+                (&GZIP_DYNAMIC[..], PROGRAM_TEXT)] {
+            let unpacked = unpack("WORK.DSK.GZ", bytes.to_vec()).unwrap();
+            assert_eq!(unpacked.contents, contents);
+            assert_eq!(unpacked.container, Container::Gzip);
+            assert_eq!(unpacked.inner_name, "WORK.DSK");
+        }
+    }
+
+    // Longer than one stored block holds, so gzip has to split it.
+    #[test]
+    fn gzip_round_trips() {
+        let contents: Vec<u8> = (0..140_000).map(|i| (i % 253) as u8).collect();
+        assert_eq!(unpack("work.po.gz", gzip(&contents)).unwrap().contents, contents);
+        assert_eq!(unpack("empty.po.gz", gzip(&[])).unwrap().contents, b"");
+    }
+
+    #[test]
+    fn gunzip_checks_the_crc() {
+        let mut bytes = GZIP_DYNAMIC;
+        bytes[GZIP_DYNAMIC.len() - 8] ^= 1;
+        let error = unpack_error("work.dsk.gz", &bytes);
+        assert_eq!(error.kind, ErrorKind::CorruptImage);
+        assert!(error.message.contains("CRC"), "{0}", error.message);
+    }
+
+    #[test]
+    fn gunzip_rejects_truncated_files() {
+        for length in [12, 20, 60, GZIP_DYNAMIC.len() - 12, GZIP_DYNAMIC.len() - 1] {
+            assert_eq!(unpack_error("work.dsk.gz", &GZIP_DYNAMIC[..length]).kind, ErrorKind::CorruptImage, "{length} bytes");
+        }
+        assert_eq!(unpack_error("work.dsk.gz", &GZIP_STORED[..16]).kind, ErrorKind::CorruptImage);
+    }
+
+    #[test]
+    fn stored_blocks_check_their_length() {
+        let mut bytes = GZIP_STORED;
+        bytes[13] ^= 1;
+        let error = unpack_error("work.dsk.gz", &bytes);
+        assert_eq!(error.kind, ErrorKind::CorruptImage);
+        assert!(error.message.contains("length check"), "{0}", error.message);
+    }
+
+    // The size in the trailer caps the output, whichever kind of block
+    // would run past it.
+    #[test]
+    fn gunzip_stops_at_the_declared_size() {
+        for vector in [&GZIP_STORED[..], &GZIP_FIXED, &GZIP_DYNAMIC] {
+            let mut bytes = vector.to_vec();
+            let at = bytes.len() - 4;
+            bytes[at] -= 1;
+            let error = unpack_error("work.dsk.gz", &bytes);
+            assert_eq!(error.kind, ErrorKind::CorruptImage);
+            assert!(error.message.contains("unpacks to more than"), "{0}", error.message);
+        }
+    }
+
+    #[test]
+    fn member_names_split_on_any_case() {
+        assert_eq!(split_member("disks.ZIP#WORK.DSK"), ("disks.ZIP", Some("WORK.DSK")));
+        assert_eq!(split_member("İİ.zip#A.PO"), ("İİ.zip", Some("A.PO")));
+        assert_eq!(split_member("İstanbul.Zip#TWO.DSK"), ("İstanbul.Zip", Some("TWO.DSK")));
+        assert_eq!(split_member("work.dsk"), ("work.dsk", None));
+    }
+
+    #[test]
+    fn unzip_picks_a_member() {
+        let unpacked = unpack("disks.zip#TWO.DSK", ZIP_TWO_MEMBERS.to_vec()).unwrap();
+        assert_eq!(unpacked.contents, b"SECOND");
+        assert_eq!(unpacked.container, Container::Zip);
+        assert_eq!(unpacked.inner_name, "TWO.DSK");
+        assert_eq!(unpack("disks.zip#one.po", ZIP_TWO_MEMBERS.to_vec()).unwrap().contents, b"FIRST");
+        assert_eq!(unpack_error("disks.zip#THREE.PO", &ZIP_TWO_MEMBERS).kind, ErrorKind::NotFound);
+    }
+
+    // With more than one image to choose from, it's the command line that
+    // has to change, not the zip.
+    #[test]
+    fn unzip_needs_a_member_named() {
+        let error = unpack_error("disks.zip", &ZIP_TWO_MEMBERS);
+        assert_eq!(error.kind, ErrorKind::Usage);
+        assert!(error.message.contains("ONE.PO, TWO.DSK"), "{0}", error.message);
+        assert_eq!(unpack_error("plain.zip#ONE.PO", b"not a zip").kind, ErrorKind::Usage);
+    }
+}
@@ -0,0 +1,44 @@
+// ANSI colours for listings and dumps. They're only used on a terminal, and
+// never when NO_COLOR is set (https://no-color.org) or --no-color is given,
+// so piped output stays plain.
+
+use std::io::IsTerminal;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Dim,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+impl Color {
+    fn code(&self) -> &'static str {
+        return match self {
+            Color::Dim => "2",
+            Color::Red => "31",
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Blue => "34",
+            Color::Magenta => "35",
+            Color::Cyan => "36",
+        };
+    }
+}
+
+pub fn enabled(no_color: bool) -> bool {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    return !no_color && !no_color_env && std::io::stdout().is_terminal();
+}
+
+// Pad text before painting it, since the escape codes count towards a
+// format width.
+pub fn paint(text: &str, color: Color, on: bool) -> String {
+    if !on {
+        return text.to_string();
+    }
+    return format!("\x1b[{}m{text}\x1b[0m", color.code());
+}
@@ -0,0 +1,77 @@
+// Errors from disk commands. Each kind has its own exit status, so scripts can
+// tell a missing file from a damaged image without parsing the message.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Usage,        // arguments that don't make sense for this image
+    NotFound,     // no file by that name
+    CorruptImage, // the image or its directory can't be read as a Pascal volume
+    Refused,      // would lose data, and wasn't told to go ahead
+    NoSpace,      // no room in the directory or on the volume
+    Io,           // reading or writing a host file failed
+}
+
+impl ErrorKind {
+    pub fn exit_code(&self) -> i32 {
+        return match self {
+            ErrorKind::Usage => 2,
+            ErrorKind::NotFound => 3,
+            ErrorKind::CorruptImage => 4,
+            ErrorKind::Refused => 5,
+            ErrorKind::NoSpace => 6,
+            ErrorKind::Io => 7,
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ErrorKind::Usage => "usage",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::CorruptImage => "corrupt_image",
+            ErrorKind::Refused => "refused",
+            ErrorKind::NoSpace => "no_space",
+            ErrorKind::Io => "io",
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub context: Vec<(&'static str, String)>, // e.g. ("image", "work.dsk")
+}
+
+impl DiskError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        return DiskError { kind, message: message.into(), context: Vec::new() };
+    }
+
+    pub fn with(mut self, key: &'static str, value: impl ToString) -> Self {
+        self.context.push((key, value.to_string()));
+        return self;
+    }
+
+    // {"error": "...", "kind": "...", "context": {...}}
+    pub fn to_json(&self) -> String {
+        let context: Vec<String> = self.context.iter()
+            .map(|(key, value)| format!("{}: {}", super::json_string(key), super::json_string(value)))
+            .collect();
+        return format!("{{\"error\": {0}, \"kind\": {1}, \"context\": {{{2}}}}}",
+            super::json_string(&self.message), super::json_string(self.kind.name()), context.join(", "));
+    }
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
+impl From<std::io::Error> for DiskError {
+    fn from(error: std::io::Error) -> Self {
+        return DiskError::new(ErrorKind::Io, error.to_string());
+    }
+}
@@ -0,0 +1,103 @@
+// Fotofiles hold a copy of the Apple II hi-res screen: 8192 bytes, 280 by 192
+// pixels. Rows aren't stored in order: each third of the screen is eight
+// interleaved groups of eight, so row y starts at
+// (y % 8) * 0x400 + (y / 8 % 8) * 0x80 + (y / 64) * 0x28.
+//
+// Each byte is seven pixels, lowest bit leftmost, and its high bit picks one
+// of two palettes. Colour comes from NTSC artifacting: two lit pixels side by
+// side are white, and a lone one is coloured by whether its column is odd or
+// even. That's the usual approximation, not an exact model of the signal.
+//
+// The PNG is written without compression, so no zlib is needed: stored
+// deflate blocks are just the bytes with a length in front.
+
+use super::archive::{crc32, deflate_stored};
+
+pub const SCREEN_SIZE: usize = 8192;
+const WIDTH: usize = 280;
+const HEIGHT: usize = 192;
+
+const BLACK: [u8; 3] = [0, 0, 0];
+const WHITE: [u8; 3] = [255, 255, 255];
+const VIOLET: [u8; 3] = [255, 68, 253];
+const GREEN: [u8; 3] = [20, 245, 60];
+const BLUE: [u8; 3] = [20, 207, 253];
+const ORANGE: [u8; 3] = [255, 106, 60];
+
+fn row_offset(y: usize) -> usize {
+    return (y % 8) * 0x400 + (y / 8 % 8) * 0x80 + (y / 64) * 0x28;
+}
+
+// One row as 280 lit/unlit pixels, each with its byte's palette bit.
+fn row_pixels(screen: &[u8], y: usize) -> Vec<(bool, bool)> {
+    let row = &screen[row_offset(y)..row_offset(y) + 40];
+    return row.iter()
+        .flat_map(|byte| (0..7).map(move |bit| (byte & (1 << bit) != 0, byte & 0x80 != 0)))
+        .collect();
+}
+
+fn color(pixels: &[(bool, bool)], x: usize) -> [u8; 3] {
+    let (on, shifted) = pixels[x];
+    if !on {
+        return BLACK;
+    }
+    let left = x > 0 && pixels[x - 1].0;
+    let right = x + 1 < WIDTH && pixels[x + 1].0;
+    if left || right {
+        return WHITE;
+    }
+    return match (shifted, x.is_multiple_of(2)) {
+        (false, true) => VIOLET,
+        (false, false) => GREEN,
+        (true, true) => BLUE,
+        (true, false) => ORANGE,
+    };
+}
+
+// A short screen is padded with black; anything past 8192 bytes is ignored.
+pub fn screen_to_png(screen: &[u8]) -> Vec<u8> {
+    let mut padded = screen[..screen.len().min(SCREEN_SIZE)].to_vec();
+    padded.resize(SCREEN_SIZE, 0);
+    let mut image = Vec::with_capacity(HEIGHT * (1 + WIDTH * 3));
+    for y in 0..HEIGHT {
+        let pixels = row_pixels(&padded, y);
+        image.push(0); // no filter
+        for x in 0..WIDTH {
+            image.extend(color(&pixels, x));
+        }
+    }
+    let mut header = Vec::new();
+    header.extend((WIDTH as u32).to_be_bytes());
+    header.extend((HEIGHT as u32).to_be_bytes());
+    header.extend([8, 2, 0, 0, 0]); // 8-bit RGB, deflate, no filtering, no interlace
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib_stored(&image));
+    chunk(&mut png, b"IEND", &[]);
+    return png;
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate_stored(data));
+    out.extend(adler32(data).to_be_bytes());
+    return out;
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    return (b << 16) | a;
+}
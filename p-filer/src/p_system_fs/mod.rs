@@ -1,23 +1,76 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
+use std::ops::Range;
+use std::path::Path;
 use std::time::SystemTime;
 use chrono::prelude::*;
+use p_code::codefile::{text_from_pages, version_name, ByteOrder, Codefile, SegmentKind};
+use p_code::hexdump;
+mod archive;
+pub mod color;
+mod error;
+mod foto;
+mod prodos;
+mod rebuild;
+use color::Color;
+pub use error::{DiskError, ErrorKind};
 
 // Directory entries are each 26 bytes. The first is a bit special, and contains information about the volume itself.
-// The rest are the files on the volume. Directory entries occupy blocks 2 through 5 on the disk.
+// The rest are the files on the volume. Directory entries occupy blocks 2 through 5 on the disk, giving room for 77
+// files, unless the volume says the directory runs further.
 #[derive(Debug)]
-#[repr(C)]
 struct Directory {
     volume: VolumeInfo,
-    entries: [DirectoryEntry; 77],
+    entries: Vec<DirectoryEntry>,
 }
 
+const ENTRY_SIZE: usize = std::mem::size_of::<DirectoryEntry>();
+
+// Every offset into the image is counted in these. Apple II disks, 5.25"
+// and 3.5" alike, and ProDOS hard disks all use 512-byte blocks.
+pub const BLOCK_SIZE: usize = 512;
+
+// The first block after a standard directory.
+const DIRECTORY_END: usize = 6;
+
+// The structs are read straight off the directory blocks, so padding or a
+// changed field would misread every entry. Fail the build instead: the
+// volume header and each entry are 26 bytes, and a standard directory holds
+// the header and 77 files.
+const _: () = assert!(ENTRY_SIZE == 26, "DirectoryEntry must be 26 bytes");
+const _: () = assert!(std::mem::size_of::<VolumeInfo>() == ENTRY_SIZE, "VolumeInfo must be the size of an entry");
+const _: () = assert!((DIRECTORY_END - 2) * BLOCK_SIZE / ENTRY_SIZE == 1 + 77, "a standard directory must hold 77 files");
+
 impl Directory {
+    // The entries array is sized to fill bytes.
     fn new(bytes: &[u8]) -> Self {
-        let directory_ptr = bytes.as_ptr() as *const Directory;
-        let new_self = unsafe {directory_ptr.read_unaligned() };
-        return new_self;
+        let volume_ptr = bytes.as_ptr() as *const VolumeInfo;
+        let volume = unsafe { volume_ptr.read_unaligned() };
+        let entries = (1..bytes.len() / ENTRY_SIZE)
+            .map(|i| {
+                let entry_ptr = bytes[i * ENTRY_SIZE..].as_ptr() as *const DirectoryEntry;
+                unsafe { entry_ptr.read_unaligned() }
+            })
+            .collect();
+        return Self { volume, entries };
+    }
+
+    // The reverse of new. Only the bytes the entries cover are replaced, so
+    // whatever follows the last entry in the directory blocks is preserved.
+    // The structs are copies of the bytes that were read, changed only where
+    // a command meant to, so fields nothing here understands, like the
+    // volume's reserved bytes, are written back as they were. Every write
+    // goes through here; nothing builds a directory from scratch.
+    fn write_to(&self, bytes: &mut [u8]) {
+        let volume_ptr = &self.volume as *const VolumeInfo as *const u8;
+        bytes[..ENTRY_SIZE].copy_from_slice(unsafe { std::slice::from_raw_parts(volume_ptr, ENTRY_SIZE) });
+        for (i, entry) in self.entries.iter().enumerate() {
+            let entry_ptr = entry as *const DirectoryEntry as *const u8;
+            let raw = unsafe { std::slice::from_raw_parts(entry_ptr, ENTRY_SIZE) };
+            bytes[(i + 1) * ENTRY_SIZE..(i + 2) * ENTRY_SIZE].copy_from_slice(raw);
+        }
     }
 }
 
@@ -30,12 +83,12 @@ struct VolumeInfo {
     volume_name: [u8; 8], // Pascal string - length is first byte
     num_blocks: u16, // number of blocks in volume
     num_files: u16, // number of files in directory
-    last_access_time: u16, // last access time - always zero?
+    last_access_time: u16, // last access time - always zero? never changed here
     date: u16, // date set by user
-    reserved: [u8; 4], // reserved for future use
+    reserved: [u8; 4], // reserved for future use; some tools keep data here, so it's never changed
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct DirectoryEntry {
     first_block: u16, // first block of file
@@ -46,15 +99,225 @@ pub struct DirectoryEntry {
     date: u16, // modified date
 }
 
-pub fn pstring_to_string(pstring: &[u8]) -> String {
-    let len = pstring[0] as usize;
-    let mut result = String::new();
-    for i in 1..=len {
-        result.push(pstring[i] as char);
+impl DirectoryEntry {
+    pub fn name(&self) -> String {
+        return pstring_to_string(&self.name);
     }
-    return result;
+
+    pub fn kind(&self) -> Option<FileKind> {
+        return FileKind::from_type(self.file_type);
+    }
+
+    // The blocks the entry says the file takes up, first to first after.
+    pub fn blocks(&self) -> Range<usize> {
+        return self.first_block as usize..self.first_after_block as usize;
+    }
+
+    fn empty() -> Self {
+        DirectoryEntry {
+            first_block: 0,
+            first_after_block: 0,
+            file_type: 0,
+            name: [0; 16],
+            bytes_in_last_block: 0,
+            date: 0,
+        }
+    }
+
+    // The blocks occupied by the file, or None if the entry's pointers don't
+    // describe a usable range. Damaged directories can have the end before the
+    // start, a zero-length file, or a file that runs off the end of the image.
+    fn block_range(&self, num_blocks: usize) -> Option<Range<usize>> {
+        let first = self.first_block as usize;
+        let after = self.first_after_block as usize;
+        if after <= first || after > num_blocks {
+            return None;
+        }
+        return Some(first..after);
+    }
+
+    // The exact length of the file: every block but the last is full. A
+    // damaged bytes_in_last_block is clamped so the length stays within the
+    // file's blocks.
+    fn byte_length(&self, range: &Range<usize>) -> usize {
+        return (range.len() - 1) * BLOCK_SIZE + (self.bytes_in_last_block as usize).min(BLOCK_SIZE);
+    }
+
+    // Some("...") when bytes_in_last_block can't be right, for a warning.
+    fn last_block_problem(&self) -> Option<String> {
+        if self.bytes_in_last_block as usize <= BLOCK_SIZE {
+            return None;
+        }
+        return Some(format!("{0}: bytes in last block is {1} ({1:#06x}), more than {BLOCK_SIZE}; using {BLOCK_SIZE}",
+            pstring_to_string(&self.name), self.bytes_in_last_block));
+    }
+}
+
+pub use p_code::pascal_string::{pstring_to_string, string_to_pstring};
+
+// Volume names live in an 8-byte field: a length byte and up to 7 characters.
+pub const VOLUME_NAME_MAX: usize = 7;
+
+// Characters the Filer won't accept in a volume name.
+const ILLEGAL_VOLUME_CHARS: &str = "=$?,:";
+
+pub fn check_volume_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("volume name is empty".to_string());
+    }
+    if name.len() > VOLUME_NAME_MAX {
+        return Err(format!("volume name {name} is longer than {VOLUME_NAME_MAX} characters"));
+    }
+    if let Some(c) = name.chars().find(|c| ILLEGAL_VOLUME_CHARS.contains(*c) || !c.is_ascii_graphic()) {
+        return Err(format!("volume name {name} contains illegal character {c:?}"));
+    }
+    return Ok(());
+}
+
+// File names live in a 16-byte field: a length byte and up to 15 characters.
+pub const FILE_NAME_MAX: usize = 15;
+
+pub fn check_file_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("file name is empty".to_string());
+    }
+    if name.len() > FILE_NAME_MAX {
+        return Err(format!("file name {name} is longer than {FILE_NAME_MAX} characters"));
+    }
+    if let Some(c) = name.chars().find(|c| ILLEGAL_VOLUME_CHARS.contains(*c) || !c.is_ascii_graphic()) {
+        return Err(format!("file name {name} contains illegal character {c:?}"));
+    }
+    return Ok(());
+}
+
+// File types, as stored in the low bits of DirectoryEntry::file_type.
+const CODE_FILE: u16 = 2;
+const TEXT_FILE: u16 = 3;
+const DATA_FILE: u16 = 5;
+
+// Every file type, for filtering listings. Subsidiary directories (type 8,
+// "securedir") are recognised but not read, since their layout varies
+// between systems. Infofiles (type 4) hold the debugger's information about
+// the codefile of the same name; their layout isn't documented, so
+// info_file shows them as bytes.
+const INFO_FILE: u16 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum FileKind {
+    Untyped,
+    Bad,
+    Code,
+    Text,
+    Info,
+    Data,
+    Graf,
+    Foto,
+    #[value(alias = "securedir")]
+    Dir,
+}
+
+impl FileKind {
+    fn from_type(file_type: u16) -> Option<Self> {
+        return match file_type & 0x0f {
+            0 => Some(FileKind::Untyped),
+            1 => Some(FileKind::Bad),
+            2 => Some(FileKind::Code),
+            3 => Some(FileKind::Text),
+            4 => Some(FileKind::Info),
+            5 => Some(FileKind::Data),
+            6 => Some(FileKind::Graf),
+            7 => Some(FileKind::Foto),
+            8 => Some(FileKind::Dir),
+            _ => None,
+        };
+    }
+
+    fn file_type(&self) -> u16 {
+        return match self {
+            FileKind::Untyped => 0,
+            FileKind::Bad => 1,
+            FileKind::Code => CODE_FILE,
+            FileKind::Text => TEXT_FILE,
+            FileKind::Info => INFO_FILE,
+            FileKind::Data => DATA_FILE,
+            FileKind::Graf => 6,
+            FileKind::Foto => 7,
+            FileKind::Dir => 8,
+        };
+    }
+
+    fn name(&self) -> &'static str {
+        return match self {
+            FileKind::Untyped => "untyped",
+            FileKind::Bad => "bad blocks",
+            FileKind::Code => "code",
+            FileKind::Text => "text",
+            FileKind::Info => "debug info",
+            FileKind::Data => "data",
+            FileKind::Graf => "graf",
+            FileKind::Foto => "foto",
+            FileKind::Dir => "subsidiary directory",
+        };
+    }
+
+    fn short_name(&self) -> &'static str {
+        return match self {
+            FileKind::Bad => "bad",
+            FileKind::Info => "info",
+            FileKind::Dir => "dir",
+            _ => self.name(),
+        };
+    }
+
+    fn color(&self) -> Color {
+        return match self {
+            FileKind::Untyped | FileKind::Dir => Color::Dim,
+            FileKind::Bad => Color::Red,
+            FileKind::Code => Color::Green,
+            FileKind::Text => Color::Cyan,
+            FileKind::Info | FileKind::Data => Color::Blue,
+            FileKind::Graf | FileKind::Foto => Color::Magenta,
+        };
+    }
+}
+
+// Like the Filer, pick a new file's type from its suffix.
+fn file_type_for_name(name: &str) -> u16 {
+    if name.ends_with(".TEXT") {
+        return TEXT_FILE;
+    }
+    if name.ends_with(".CODE") {
+        return CODE_FILE;
+    }
+    if name.ends_with(".INFO") {
+        return INFO_FILE;
+    }
+    return DATA_FILE;
 }
 
+// Files with a special role on an Apple Pascal system disk.
+const SYSTEM_FILES: [(&str, &str); 14] = [
+    ("SYSTEM.APPLE", "p-code interpreter"),
+    ("SYSTEM.PASCAL", "operating system"),
+    ("SYSTEM.MISCINFO", "terminal and system configuration"),
+    ("SYSTEM.CHARSET", "graphics character set"),
+    ("SYSTEM.STARTUP", "program run at boot"),
+    ("SYSTEM.LIBRARY", "library of units"),
+    ("SYSTEM.COMPILER", "Pascal compiler"),
+    ("SYSTEM.SYNTAX", "compiler error messages"),
+    ("SYSTEM.EDITOR", "screen editor"),
+    ("SYSTEM.FILER", "Filer"),
+    ("SYSTEM.LINKER", "linker"),
+    ("SYSTEM.ASSMBLER", "6502 assembler"),
+    ("SYSTEM.WRK.TEXT", "work file source"),
+    ("SYSTEM.WRK.CODE", "work file code"),
+];
+
+// Boot block signatures of releases, as boot_signature prints them. Only
+// signatures taken from known-good release disks belong here; it starts
+// empty, and until it fills the signature is for matching by hand.
+const KNOWN_BOOT_SIGNATURES: &[(u32, &str)] = &[];
+
 struct PdateYDM {
     // These types picked to be friendly for conversion to system time.
     year: i32,
@@ -97,201 +360,2332 @@ pub fn pdate_to_systime(pdate: u16) -> SystemTime {
         Local.with_ymd_and_hms(ydm.year, ydm.month, ydm.day, 0, 0, 0).unwrap());
 }
 
+// The reverse of PdateYDM::new: month in bits 0-3, day in bits 4-8 and the
+// year, less 1900 or 2000, in bits 9-15. Only 1970-2069 can be told apart
+// when reading, so other years are refused rather than wrapped.
+pub fn encode_pdate(year: i32, month: u32, day: u32) -> Result<u16, String> {
+    if !(1970..2070).contains(&year) {
+        return Err(format!("{year} can't be stored in a Pascal date, which only holds 1970-2069"));
+    }
+    if NaiveDate::from_ymd_opt(year, month, day).is_none() {
+        return Err(format!("{year:04}-{month:02}-{day:02} is not a date"));
+    }
+    let offset = if year >= 2000 { year - 2000 } else { year - 1900 };
+    return Ok(((offset as u16) << 9) | ((day as u16) << 4) | month as u16);
+}
+
+// The local date of a host file time, as pdate_to_systime reads it back.
+pub fn systime_to_pdate(time: SystemTime) -> Result<u16, String> {
+    let date = DateTime::<Local>::from(time).date_naive();
+    return encode_pdate(date.year(), date.month(), date.day());
+}
+
+// Whether the month and day could be a real date. Unset dates are zero.
+fn pdate_is_valid(pdate: u16) -> bool {
+    let ydm = PdateYDM::new(pdate);
+    return (1..=12).contains(&ydm.month) && (1..=31).contains(&ydm.day);
+}
+
 pub fn pdate_to_string(pdate: u16) -> String {
     let ydm = PdateYDM::new(pdate);
 
     return format!("{:04}-{:02}-{:02}", ydm.year, ydm.month, ydm.day);
 }
 
-pub fn text_from_blocks(buffer: &[u8]) -> Vec<u8> {
-    let mut result = Vec::new();
-    let mut skip_next = false;
-    for i in 1024..buffer.len() {
-        let byte = buffer[i];
-        if skip_next {
-            skip_next = false;
-            continue;
-        }
-        if byte == 0x0d {
-            result.push(0x0a); // convert CR to LF
-        } else if byte == 0x10 {
-            let space_count = buffer[i+1] as usize - 32;
-            for _ in 0..space_count {
-                result.push(0x20); // emit spaces for indent
-            }
-            skip_next = true; // skip the next byte
-        } else if byte == 0 {
-            continue; // skip null bytes
+// Apple Pascal textfiles are made of 1024-byte pages, two blocks each, and
+// start with a header page the editor keeps its own state in. Other p-system
+// implementations can use a different page size, which is also the size of
+// the header.
+pub const TEXT_PAGE_SIZE: usize = 1024;
+
+// Pages are whole blocks, since textfiles are read and written a block at a
+// time.
+pub fn parse_page_size(value: &str) -> Result<usize, String> {
+    let size: usize = value.parse().map_err(|_| format!("{value} is not a number"))?;
+    if size == 0 || !size.is_multiple_of(BLOCK_SIZE) {
+        return Err(format!("{size} is not a whole number of {BLOCK_SIZE}-byte blocks"));
+    }
+    return Ok(size);
+}
+
+// Decode a textfile: skip the header page, expand DLE-compressed indents and
+// drop the NUL padding at the end of each page. Lines end in CR on disk, which
+// is converted to LF unless keep_cr is set.
+pub fn text_from_blocks(buffer: &[u8], keep_cr: bool, page_size: usize) -> Vec<u8> {
+    return text_from_pages(&buffer[buffer.len().min(page_size)..], keep_cr);
+}
+
+// The reverse of text_from_blocks: a blank header page, then pages of
+// CR-terminated lines with leading spaces compressed to DLE and a count.
+// Lines aren't split across pages unless they're longer than a page, and the
+// end of each page is padded with NULs.
+pub fn text_to_blocks(text: &[u8], page_size: usize) -> Vec<u8> {
+    let mut result = vec![0u8; page_size];
+    let mut page: Vec<u8> = Vec::with_capacity(page_size);
+    let mut lines: Vec<&[u8]> = text.split(|c| *c == 0x0a).collect();
+    if lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop(); // text ending in a newline doesn't start another line
+    }
+    for line in lines {
+        let line = line.strip_suffix(&[0x0d]).unwrap_or(line);
+        let indent = line.iter().take_while(|c| **c == 0x20).count().min(255 - 32);
+        let mut encoded = Vec::with_capacity(line.len() + 3);
+        if indent > 0 {
+            encoded.push(0x10);
+            encoded.push(32 + indent as u8);
+        }
+        encoded.extend_from_slice(&line[indent..]);
+        encoded.push(0x0d);
+        if !page.is_empty() && page.len() + encoded.len() > page_size {
+            page.resize(page_size, 0);
+            result.append(&mut page);
+        }
+        page.extend(encoded);
+        while page.len() >= page_size {
+            result.extend(page.drain(..page_size));
+        }
+    }
+    if !page.is_empty() {
+        page.resize(page_size, 0);
+        result.append(&mut page);
+    }
+    return result;
+}
+
+// The order sectors are stored in within the image file. DOS-ordered images
+// need de-interleaving to get the blocks in order. ProDOS-ordered images,
+// including hard disk images, are already in block order. Images from
+// unusual tools can be read with a map of their own, in the same form as
+// SECTOR_MAP, with an entry for each sector of a track.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SectorOrder {
+    Dos,
+    ProDos,
+    Custom(Vec<usize>),
+}
+
+impl SectorOrder {
+    pub fn from_image_name(name: &str) -> Self {
+        let lower = name.to_lowercase();
+        if lower.ends_with(".po") || lower.ends_with(".hdv") {
+            return SectorOrder::ProDos;
+        }
+        return SectorOrder::Dos;
+    }
+
+    // Parse an --interleave value: "none" for straight block order, "dos",
+    // or comma-separated sector numbers, one for each sector of a track,
+    // which must use each of 0..count once.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "none" => return Ok(SectorOrder::ProDos),
+            "dos" => return Ok(SectorOrder::Dos),
+            _ => {}
+        }
+        let numbers = value.split(',')
+            .map(|n| n.trim().parse::<usize>().map_err(|_| format!("{0} is not a sector number", n.trim())))
+            .collect::<Result<Vec<usize>, String>>()?;
+        for sector in 0..numbers.len() {
+            if !numbers.contains(&sector) {
+                return Err(format!("interleave map is not a permutation of 0..{0}: sector {sector} is missing", numbers.len() - 1));
+            }
+        }
+        return Ok(SectorOrder::Custom(numbers));
+    }
+}
+
+// The shape of the image's tracks, for media other than 16-sector Apple II
+// floppies. The number of tracks is worked out from the image's length
+// unless it's given, in which case the length has to match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geometry {
+    pub tracks: Option<usize>,
+    pub sectors: usize,     // per track
+    pub sector_size: usize, // in bytes
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        return Geometry { tracks: None, sectors: 16, sector_size: 256 };
+    }
+}
+
+impl Geometry {
+    // .d13 images are of 13-sector disks. The name is only trusted when the
+    // geometry is the default, since that's all a given one can be told from.
+    fn for_image(&self, name: &str) -> Geometry {
+        if *self == Geometry::default() && name.to_lowercase().ends_with(".d13") {
+            return Geometry { sectors: SECTOR_MAP_13.len(), ..*self };
+        }
+        return *self;
+    }
+
+    fn check_length(&self, length: usize) -> Result<(), DiskError> {
+        let Some(tracks) = self.tracks else {
+            return Ok(());
+        };
+        let expected = tracks * self.sectors * self.sector_size;
+        if expected != length {
+            return Err(DiskError::new(ErrorKind::Usage, format!(
+                "{tracks} tracks of {0} {1}-byte sectors is {expected} bytes, but the image is {length}",
+                self.sectors, self.sector_size)).with("length", length));
+        }
+        return Ok(());
+    }
+}
+
+// Where each sector of the volume is in the image file: with a map, tracks
+// of map.len() sectors, each stored where the map says; without one, in
+// block order.
+#[derive(Debug, Clone, PartialEq)]
+struct Layout {
+    map: Option<Vec<usize>>,
+    sector_size: usize,
+}
+
+impl Layout {
+    fn new(order: &SectorOrder, geometry: &Geometry) -> Result<Self, DiskError> {
+        let map = match order {
+            SectorOrder::ProDos => None,
+            SectorOrder::Dos => match dos_sector_map(geometry.sectors) {
+                Some(map) => Some(map.to_vec()),
+                None => return Err(DiskError::new(ErrorKind::Usage, format!(
+                    "the DOS sector order is for 16- or 13-sector tracks, not {0}: give --interleave none, or a map of {0} sectors",
+                    geometry.sectors))),
+            },
+            SectorOrder::Custom(map) if map.len() != geometry.sectors => {
+                return Err(DiskError::new(ErrorKind::Usage, format!(
+                    "the interleave map has {0} sectors, but tracks have {1}", map.len(), geometry.sectors)));
+            }
+            SectorOrder::Custom(map) => Some(map.clone()),
+        };
+        return Ok(Layout { map, sector_size: geometry.sector_size });
+    }
+
+    // The layout of an image written from scratch, from its name.
+    fn standard(name: &str) -> Self {
+        let geometry = Geometry::default().for_image(name);
+        let map = match SectorOrder::from_image_name(name) {
+            SectorOrder::Dos => dos_sector_map(geometry.sectors).map(<[usize]>::to_vec),
+            _ => None,
+        };
+        return Layout { map, sector_size: geometry.sector_size };
+    }
+
+    // Only whole tracks can be de-interleaved, so images are read and
+    // written in these.
+    fn track_len(&self) -> Option<usize> {
+        return self.map.as_ref().map(|map| map.len() * self.sector_size);
+    }
+
+    // Where the nth sector of the volume is in the file.
+    fn sector_offset(&self, n: usize) -> usize {
+        return match &self.map {
+            Some(map) => (n / map.len()) * map.len() * self.sector_size + map[n % map.len()] * self.sector_size,
+            None => n * self.sector_size,
+        };
+    }
+
+    // Where each sector of the volume is read from, on stderr, for an image
+    // that won't parse. Sector numbers are within the track: the image's,
+    // then the volume's.
+    fn trace(&self, length: usize) {
+        let Some(map) = &self.map else {
+            eprintln!("No interleave: the image is in block order");
+            return;
+        };
+        for n in 0..length / self.sector_size {
+            let (track, sector) = (n / map.len(), n % map.len());
+            eprintln!("track {track}: sector {0} -> {sector} (offset {1:#x} -> {2:#x}, block {3})",
+                map[sector], self.sector_offset(n), n * self.sector_size, n * self.sector_size / BLOCK_SIZE);
+        }
+    }
+
+    fn deinterleave(&self, contents: &[u8]) -> Vec<u8> {
+        let Some(track_len) = self.track_len() else {
+            return contents.to_vec();
+        };
+        let mut buffer = Vec::with_capacity(contents.len());
+        for n in 0..contents.len() / track_len * (track_len / self.sector_size) {
+            let offset = self.sector_offset(n);
+            buffer.extend_from_slice(&contents[offset..offset + self.sector_size]);
+        }
+        return buffer;
+    }
+
+    fn interleave(&self, blocks: &[u8]) -> Vec<u8> {
+        if self.map.is_none() {
+            return blocks.to_vec();
+        }
+        let mut contents = vec![0u8; blocks.len()];
+        for (n, sector) in blocks.chunks(self.sector_size).enumerate() {
+            let target = self.sector_offset(n);
+            contents[target..target + sector.len()].copy_from_slice(sector);
+        }
+        return contents;
+    }
+}
+
+// Where to find the Pascal volume within an image.
+#[derive(Debug, Default, Clone)]
+pub struct DiskOptions {
+    pub offset: usize,               // blocks into the image (or ProDOS file)
+    pub prodos_path: Option<String>, // ProDOS file holding the volume
+    pub interleave: Option<SectorOrder>, // instead of guessing from the name
+    pub geometry: Geometry,          // sectors per track and their size
+    pub text_page_size: usize,       // TEXT_PAGE_SIZE, unless it's not Apple Pascal
+    pub color: bool,                 // colour listings and dumps
+    pub raw_dates: bool,             // show dates as stored, as well as interpreted
+    pub quiet: bool,                 // say how the image was read on stderr, leaving stdout to the result
+    pub backup: bool,                // copy the image to .bak before writing it
+    pub trace_interleave: bool,      // log where each sector is read from, on stderr
+}
+
+// How transfer reads and writes a file.
+#[derive(Debug, Default, Clone)]
+pub struct TransferOptions {
+    pub is_text: bool,       // convert between textfile and plain text
+    pub preserve_date: bool, // carry the file's date across
+    pub keep_cr: bool,       // leave CR line endings in extracted text
+    pub force: bool,         // overwrite an existing host file
+    pub as_blocks: bool,     // copy a textfile as it is on disk, header page and all
+    pub at_block: Option<usize>, // where to put a file on the image, instead of first fit
+    pub interface: bool,     // copy a unit's interface text instead of its code
+    pub convert_foto: bool,  // copy a fotofile's screen as a PNG
+}
+
+// Messages about opening the image normally go with the command's output,
+// but a command printing only a result for scripts wants stdout to itself.
+fn note(quiet: bool, message: &str) {
+    if quiet {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
+
+// The order files are taken in by commands that go through all of them.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum FileOrder {
+    /// Alphabetical by name
+    Name,
+    /// As they are in the directory, which is the order of their blocks
+    Directory,
+}
+
+// How bytes are shown as characters in dumps.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Encoding {
+    /// Printable ASCII only
+    Ascii,
+    /// ISO 8859-1
+    Latin1,
+    /// Apple II text, where the high bit is usually set
+    Apple,
+    /// IBM PC code page 437
+    Cp437,
+}
+
+const CP437_HIGH: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»",
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■ ",
+);
+
+impl Encoding {
+    // Control characters are shown as '.' in every encoding.
+    pub fn display(&self, byte: u8) -> char {
+        let c = match self {
+            Encoding::Apple => byte & 0x7f,
+            _ => byte,
+        };
+        if c < 0x20 || c == 0x7f {
+            return '.';
+        }
+        if c < 0x7f {
+            return c as char;
+        }
+        return match self {
+            Encoding::Latin1 if c >= 0xa0 => char::from(c),
+            Encoding::Cp437 => CP437_HIGH.chars().nth(c as usize - 0x80).unwrap_or('.'),
+            _ => '.',
+        };
+    }
+}
+
+// Format sorted block numbers compactly, e.g. "3, 7-12, 20".
+fn format_block_ranges(blocks: &[usize]) -> String {
+    let mut parts = Vec::new();
+    let mut i = 0;
+    while i < blocks.len() {
+        let start = blocks[i];
+        while i + 1 < blocks.len() && blocks[i + 1] == blocks[i] + 1 {
+            i += 1;
+        }
+        if blocks[i] == start {
+            parts.push(start.to_string());
         } else {
-            result.push(byte);
+            parts.push(format!("{start}-{}", blocks[i]));
+        }
+        i += 1;
+    }
+    return parts.join(", ");
+}
+
+// Parse bytes written in hex, like "00 06 00" or "00,06,00".
+fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|b| !b.is_empty())
+        .map(|b| u8::from_str_radix(b, 16).map_err(|_| format!("{b} is not a hex byte")))
+        .collect::<Result<Vec<u8>, String>>()?;
+    if bytes.is_empty() {
+        return Err("No bytes given".to_string());
+    }
+    return Ok(bytes);
+}
+
+// Quote a string for JSON output.
+fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
         }
     }
+    result.push('"');
     return result;
-}        
+}
+
+// A name for a Pascal file that's safe on the host. Characters most
+// filesystems reject become '_', and a name that matches one already used,
+// ignoring case, gets a numeric suffix before its extension.
+fn host_file_name(name: &str, used: &mut HashSet<String>) -> String {
+    let mut safe: String = name.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    if safe.is_empty() || safe.chars().all(|c| c == '.') {
+        safe = format!("_{safe}");
+    }
+    let (stem, extension) = match safe.rfind('.') {
+        Some(dot) if dot > 0 => (&safe[..dot], &safe[dot..]),
+        _ => (safe.as_str(), ""),
+    };
+    let mut candidate = safe.clone();
+    let mut n = 2;
+    while used.contains(&candidate.to_lowercase()) {
+        candidate = format!("{stem}-{n}{extension}");
+        n += 1;
+    }
+    used.insert(candidate.to_lowercase());
+    return candidate;
+}
+
+// Apple II .dsk files have interleaved sectors. Entry n is the DOS sector
+// holding the nth 256-byte half-block of a track.
+const SECTOR_MAP: [usize; 16] = [
+    0, 14, 13, 12, 11, 10, 9, 8,
+    7, 6, 5, 4, 3, 2, 1, 15
+];
+
+// The same for 13-sector disks in DOS 3.2 order, as .d13 images are. Both
+// tables are worked out the same way. On 16 sectors the nth half-block is
+// physical sector 2n mod 15, with 15 last, and DOS 3.3 keeps its logical
+// sector l at physical 13l mod 15. On 13 the half-block is at 2n mod 13,
+// and DOS 3.2 keeps l at 10l mod 13.
+const SECTOR_MAP_13: [usize; 13] = [
+    0, 8, 3, 11, 6, 1, 9, 4,
+    12, 7, 2, 10, 5
+];
+
+// The DOS-order map for tracks of this many sectors, if there is one.
+fn dos_sector_map(sectors: usize) -> Option<&'static [usize]> {
+    return match sectors {
+        16 => Some(&SECTOR_MAP),
+        13 => Some(&SECTOR_MAP_13),
+        _ => None,
+    };
+}
+
+// One entry as list shows it.
+struct ListRow {
+    index: usize,
+    name: String,
+    type_name: &'static str,
+    type_color: Color,
+    range: Option<Range<usize>>,
+    blocks: String,
+    bytes: String,
+    sum: String,
+}
 
 pub struct AppleDisk {
     image: String,
     blocks: Vec<u8>,
+    layout: Layout,
+    base: usize, // first block of the Pascal volume within blocks
+    directory_end: usize, // first block after the directory, within the volume
+    text_page_size: usize,
+    color: bool,
+    raw_dates: bool, // show the stored year and word with each date
+    backup: bool, // copy the image to .bak before writing it
+    container: archive::Container, // how the image is kept on disk
+    available: usize, // blocks the image holds for the volume
     directory: Directory,
 }
 
 impl AppleDisk {
     pub fn read_blocks(&self, index: usize, count: usize) -> &[u8] {
-        let start:usize = index * 512;
-        let end:usize = (index + count) * 512;
+        let start:usize = (self.base + index) * BLOCK_SIZE;
+        let end:usize = (self.base + index + count) * BLOCK_SIZE;
         return &self.blocks[start..end]
     }
 
+    // One block of the volume, or None past its end.
+    pub fn block(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.volume_end() {
+            return None;
+        }
+        return Some(self.read_blocks(index, 1));
+    }
+
+    // Every block of the image, de-interleaved, including any before the
+    // volume's --offset or ProDOS file.
+    pub fn raw(&self) -> &[u8] {
+        return &self.blocks;
+    }
+
     pub fn num_blocks(&self) -> usize {
-        return self.blocks.len() / 512
+        return self.blocks.len() / BLOCK_SIZE - self.base
+    }
+
+    // An image named "-" is read from standard input.
+    pub fn new(name: &str, options: &DiskOptions) -> Result<Self, DiskError> {
+        let contents: Vec<u8> = if name == "-" {
+            let mut contents = Vec::new();
+            std::io::stdin().read_to_end(&mut contents).map_err(|e| DiskError::from(e).with("image", name))?;
+            contents
+        } else {
+            fs::read(archive::split_member(name).0).map_err(|e| DiskError::from(e).with("image", name))?
+        };
+        return Self::from_bytes(name, contents, options);
     }
 
-    pub fn new(name: &str) -> Self {
-        let buffer = Self::read_buffer(&name);
-        let directory = Directory::new(&buffer[1024..2560]);
-        Self {
+    // A gzipped image, or one in a zip, is unpacked first. Its sector order
+    // comes from the name of the image inside.
+    fn unpack(name: &str, contents: Vec<u8>) -> Result<archive::Unpacked, DiskError> {
+        return archive::unpack(name, contents).map_err(|e| e.with("image", name));
+    }
+
+    // Build a disk from image bytes already in memory. The name is used for
+    // messages and to pick the sector order from its extension.
+    pub fn from_bytes(name: &str, contents: Vec<u8>, options: &DiskOptions) -> Result<Self, DiskError> {
+        return Self::from_unpacked(name, Self::unpack(name, contents)?, options);
+    }
+
+    fn from_unpacked(name: &str, unpacked: archive::Unpacked, options: &DiskOptions) -> Result<Self, DiskError> {
+        let layout = Self::layout(&unpacked, options).map_err(|e| e.with("image", name))?;
+        let container = unpacked.container;
+        let buffer = Self::read_buffer(unpacked.contents, &layout, options).map_err(|e| e.with("image", name))?;
+        let mut base = options.offset;
+        let mut available = (buffer.len() / BLOCK_SIZE).saturating_sub(base);
+        if let Some(path) = &options.prodos_path {
+            let (first, count) = prodos::find_contiguous_file(&buffer, path)
+                .map_err(|e| DiskError::new(ErrorKind::NotFound, e).with("image", name).with("prodos_path", path))?;
+            note(options.quiet, &format!("Found {path} at block {first}, {count} blocks"));
+            base += first;
+            available = count.saturating_sub(options.offset);
+        }
+        return Self::from_buffer(name, buffer, layout, base, available, container, options);
+    }
+
+    // From --interleave and the geometry options, or else the image's name.
+    fn layout(unpacked: &archive::Unpacked, options: &DiskOptions) -> Result<Layout, DiskError> {
+        let geometry = options.geometry.for_image(&unpacked.inner_name);
+        geometry.check_length(unpacked.contents.len())?;
+        let order = options.interleave.clone().unwrap_or_else(|| SectorOrder::from_image_name(&unpacked.inner_name));
+        return Layout::new(&order, &geometry);
+    }
+
+    // Like from_bytes, but only the tracks up to the end of the directory are
+    // de-interleaved, for catalogue tools that read many images and only need
+    // their file lists. The disk can list and check its directory, but its
+    // files' blocks aren't there to read, so they're shown as unreadable and
+    // saving is refused: use from_bytes for those. A volume in a ProDOS file
+    // could be anywhere in the image, and an image that isn't whole tracks
+    // gets from_bytes' error, so both are read in full.
+    pub fn directory_only(name: &str, contents: Vec<u8>, options: &DiskOptions) -> Result<Self, DiskError> {
+        let unpacked = Self::unpack(name, contents)?;
+        let layout = Self::layout(&unpacked, options).map_err(|e| e.with("image", name))?;
+        let track_len = layout.track_len().unwrap_or(BLOCK_SIZE);
+        if options.prodos_path.is_some() || !unpacked.contents.len().is_multiple_of(track_len) {
+            return Self::from_unpacked(name, unpacked, options);
+        }
+        let contents = &unpacked.contents;
+        let prefix = |blocks: usize| {
+            let length = (blocks * BLOCK_SIZE).next_multiple_of(track_len).min(contents.len());
+            return layout.deinterleave(&contents[..length]);
+        };
+        let base = options.offset;
+        let mut buffer = prefix(base + DIRECTORY_END);
+        if (base + DIRECTORY_END) * BLOCK_SIZE <= buffer.len() {
+            let header = (base + 2) * BLOCK_SIZE;
+            let end = Directory::new(&buffer[header..header + ENTRY_SIZE]).volume.first_block_after_directory as usize;
+            if end > DIRECTORY_END {
+                buffer = prefix(base + end);
+            }
+        }
+        let available = (contents.len() / BLOCK_SIZE).saturating_sub(base);
+        return Self::from_buffer(name, buffer, layout.clone(), base, available, unpacked.container, options);
+    }
+
+    // The rest of opening a disk, once its blocks are in order.
+    fn from_buffer(name: &str, buffer: Vec<u8>, layout: Layout, base: usize, available: usize,
+        container: archive::Container, options: &DiskOptions) -> Result<Self, DiskError> {
+        if (base + DIRECTORY_END) * BLOCK_SIZE > buffer.len() {
+            return Err(DiskError::new(ErrorKind::Usage,
+                format!("offset {base} leaves no room for a volume in {0} blocks", buffer.len() / BLOCK_SIZE))
+                .with("image", name).with("offset", base));
+        }
+        let directory_end = Self::directory_end(&buffer, base, options.quiet);
+        let directory = Directory::new(&buffer[(base + 2) * BLOCK_SIZE..(base + directory_end) * BLOCK_SIZE]);
+        let disk = Self {
             image: name.to_string(),
             blocks: buffer,
-            directory: directory
+            layout,
+            base,
+            directory_end,
+            text_page_size: options.text_page_size,
+            color: options.color,
+            raw_dates: options.raw_dates,
+            backup: options.backup,
+            container,
+            available,
+            directory
+        };
+        let declared = disk.directory.volume.num_blocks as usize;
+        if let Some(problem) = disk.size_problem() {
+            note(options.quiet, &format!("Warning: {problem}"));
+        } else if declared < available {
+            note(options.quiet, &format!("The volume has {declared} blocks; the other {0} in the image are ignored", available - declared));
         }
+        return Ok(disk);
     }
 
-    fn read_buffer(name: &str) -> Vec<u8> {
-        let contents: Vec<u8> = fs::read(&name) .expect("couldn't read file");
-        let mut buffer = Vec::with_capacity(contents.len());
-        // Apple II .dsk files have interleaved sectors, so un-shuffle them
-        let sector_map: [usize; 16] = [
-            0, 14, 13, 12, 11, 10, 9, 8,
-            7, 6, 5, 4, 3, 2, 1, 15
-        ];
-        let total_sectors = contents.len() / 256;
-        let num_tracks = total_sectors / 16;
-        println!("{num_tracks} tracks of 16 sectors = {total_sectors} sectors, {0} blocks", total_sectors/2);
-        for track in 0..num_tracks {
-            let track_offset = track * 16 * 256;
-            //println!("track {track}, offset {track_offset}");
-            for sector in 0..16 as usize {
-                let sector2 = sector_map[sector];
-                //println!("track: {track}, sector {sector2} -> {sector}");
-                //let target_sector_offset = sector * 256 + track_offset;
-                let source_sector_offset = sector2 * 256 + track_offset;
-                //println!("");
-                for byte in 0..256 as usize {
-                    buffer.push(contents[source_sector_offset+byte]);
-                }
-            }
+    // Some volumes have a directory longer than the usual four blocks, which
+    // they record in first_block_after_directory. Anything that doesn't fit
+    // in the image is ignored, and the standard directory is read instead.
+    fn directory_end(buffer: &[u8], base: usize, quiet: bool) -> usize {
+        let volume = Directory::new(&buffer[(base + 2) * BLOCK_SIZE..(base + 2) * BLOCK_SIZE + ENTRY_SIZE]).volume;
+        let end = volume.first_block_after_directory as usize;
+        if end <= DIRECTORY_END || (base + end) * BLOCK_SIZE > buffer.len() || end > volume.num_blocks as usize {
+            return DIRECTORY_END;
         }
-        //println!("file len: {}, buffer len: {}", contents.len(), self.buffer.len());
-        assert!(contents.len() == buffer.len());
-        return buffer;
+        note(quiet, &format!("Directory occupies blocks 2..{end}, with room for {0} files", ((end - 2) * BLOCK_SIZE) / ENTRY_SIZE - 1));
+        return end;
     }
 
-    pub fn list(&self) {
-        println!("Listing files on {0}", self.image);
-        println!("First block (should be 0): {}", self.directory.volume.first_system_block);
-        println!("First block after directory (should be 6): {}", self.directory.volume.first_block_after_directory);
-        println!("File type (should be 0): {}", self.directory.volume.file_type);
-        println!("Volume name:      {}", pstring_to_string(&self.directory.volume.volume_name));
-        println!("Number of blocks: {}", self.directory.volume.num_blocks);
-        println!("Number of files:  {}", self.directory.volume.num_files);
-        println!("Last access time: {}", self.directory.volume.last_access_time);
-        println!("Date:             {}", pdate_to_string(self.directory.volume.date));
-        println!("Reserved:         {:?}", self.directory.volume.reserved);
-        for index in 0..self.directory.volume.num_files {
-            let entry = &self.directory.entries[index as usize];
-            println!("Entry {index}:");
-            println!("  First block:         {}", entry.first_block);
-            println!("  First block after:   {}", entry.first_after_block);
-            println!("  File type:           {}", entry.file_type);
-            println!("  Name:                {}", pstring_to_string(&entry.name));
-            println!("  Bytes in last block: {}", entry.bytes_in_last_block);
-            println!("  Date:                {}", pdate_to_string(entry.date));
+    fn read_buffer(contents: Vec<u8>, layout: &Layout, options: &DiskOptions) -> Result<Vec<u8>, DiskError> {
+        let quiet = options.quiet;
+        if options.trace_interleave {
+            layout.trace(contents.len());
         }
+        let Some(track_len) = layout.track_len() else {
+            note(quiet, &format!("{0} blocks in ProDOS order", contents.len() / BLOCK_SIZE));
+            return Ok(contents);
+        };
+        // Only whole tracks can be de-interleaved. A partial track would be
+        // dropped, so stop and say why instead.
+        if !contents.len().is_multiple_of(track_len) {
+            return Err(DiskError::new(ErrorKind::CorruptImage, format!(
+                "image is {0} bytes, which is not a whole number of {track_len}-byte tracks \
+                ({1} tracks and {2} bytes over; a 140K floppy is 143360 bytes). \
+                It may be truncated, or not a 16-sector DOS-order floppy: \
+                try --interleave none for block-ordered or hard disk images",
+                contents.len(), contents.len() / track_len, contents.len() % track_len))
+                .with("length", contents.len()));
+        }
+        let total_sectors = contents.len() / layout.sector_size;
+        let num_tracks = contents.len() / track_len;
+        note(quiet, &format!("{num_tracks} tracks of {0} sectors = {total_sectors} sectors, {1} blocks",
+            track_len / layout.sector_size, contents.len() / BLOCK_SIZE));
+        return Ok(layout.deinterleave(&contents));
     }
-    
-    pub fn remove(&self, name: &str) {
-        println!("Removing {name} on {0}", self.image);
+
+    // Put the directory back into the block buffer and write the whole image
+    // out to its file.
+    // Images read from standard input can't be saved, since standard output
+    // already carries the command's messages.
+    pub fn save(&mut self) -> Result<(), DiskError> {
+        self.check_whole()?;
+        self.store_directory()?;
+        self.back_up()?;
+        let mut contents = self.layout.interleave(&self.blocks);
+        if self.container == archive::Container::Gzip {
+            contents = archive::gzip(&contents);
+        }
+        fs::write(&self.image, contents).map_err(|e| DiskError::from(e).with("image", &self.image))?;
+        println!("Saved {0}", self.image);
+        return Ok(());
     }
-    
-    pub fn transfer(&self, name: &str, to_image: bool, is_text: bool,
-        preserve_date: bool) {
-        if to_image {
-            println!("Copying {name} to {0}", self.image);
-            todo!("Copying to image not implemented yet");
+
+    // Like save, but only the directory's sectors are written, each to
+    // wherever the sector order puts it in the file. Commands that only
+    // change the directory use this, so the rest of a large image is left
+    // alone.
+    fn write_directory(&mut self) -> Result<(), DiskError> {
+        self.check_whole()?;
+        // A gzipped image can't be written in place.
+        if self.container != archive::Container::Plain {
+            return self.save();
+        }
+        let region = self.store_directory()?;
+        self.back_up()?;
+        let mut file = fs::OpenOptions::new().write(true).open(&self.image)
+            .map_err(|e| DiskError::from(e).with("image", &self.image))?;
+        let size = self.layout.sector_size;
+        for sector in region.start / size..region.end.div_ceil(size) {
+            let offset = self.layout.sector_offset(sector);
+            let bytes = &self.blocks[sector * size..((sector + 1) * size).min(self.blocks.len())];
+            file.seek(std::io::SeekFrom::Start(offset as u64))
+                .and_then(|_| file.write_all(bytes))
+                .map_err(|e| DiskError::from(e).with("image", &self.image))?;
+        }
+        println!("Saved the directory of {0}", self.image);
+        return Ok(());
+    }
+
+    // Both ways of writing the image copy it first, as it is on disk, to the
+    // same name with .bak added. A later write replaces the copy, so it
+    // always holds the image from just before the last change.
+    fn back_up(&self) -> Result<(), DiskError> {
+        if !self.backup {
+            return Ok(());
+        }
+        let backup = format!("{0}.bak", self.image);
+        fs::copy(&self.image, &backup).map_err(|e| DiskError::from(e).with("path", &backup))?;
+        println!("Backed up {0} to {backup}", self.image);
+        return Ok(());
+    }
+
+    // Put the directory back into the block buffer, returning the bytes it
+    // covers.
+    fn store_directory(&mut self) -> Result<Range<usize>, DiskError> {
+        if self.image == "-" {
+            return Err(self.error(ErrorKind::Refused, "The image was read from standard input, so the changes were not saved"));
+        }
+        if self.container == archive::Container::Zip {
+            return Err(self.error(ErrorKind::Refused, "The image is in a zip file, which can't be written back; unzip it first"));
+        }
+        let start = (self.base + 2) * BLOCK_SIZE;
+        let end = (self.base + self.directory_end) * BLOCK_SIZE;
+        self.directory.write_to(&mut self.blocks[start..end]);
+        return Ok(start..end);
+    }
+
+    // Every mutating command prints what it's going to do first, then either
+    // stops there or saves.
+    fn commit(&mut self, dry_run: bool) -> Result<(), DiskError> {
+        if dry_run {
+            println!("Dry run, {0} was not changed", self.image);
+            return Ok(());
+        }
+        return self.save();
+    }
+
+    // commit, for commands that only change the directory.
+    fn commit_directory(&mut self, dry_run: bool) -> Result<(), DiskError> {
+        if dry_run {
+            println!("Dry run, {0} was not changed", self.image);
+            return Ok(());
+        }
+        return self.write_directory();
+    }
+
+    fn paint(&self, text: &str, color: Color) -> String {
+        return color::paint(text, color, self.color);
+    }
+
+    fn warning(&self, message: &str) -> String {
+        return format!("{0} {message}", self.paint("Warning:", Color::Yellow));
+    }
+
+    // The stored year is only two digits, and which century it's in is a
+    // guess, so raw_dates also shows it and the packed word as they are.
+    fn date(&self, pdate: u16, width: usize) -> String {
+        let date = if self.raw_dates {
+            format!("{0} (year {1}, {pdate:#06x})", pdate_to_string(pdate), pdate >> 9)
         } else {
-            println!("Copying {name} from {0}", self.image);
-            for entry in &self.directory.entries {
-                let entry_name = pstring_to_string(&entry.name);
-                if entry_name == name {
-                    println!("Found {name} at block {0}", entry.first_block);
-                    let file_buffer = self.read_blocks(entry.first_block as usize, entry.first_after_block as usize - entry.first_block as usize);
-                    let file_name = format!("{name}");
-                    // Because we want to possibly use set_times, we'll
-                    // have to use more conventional File:: methods.
-                    let mut filedesc = File::create(file_name).expect("create failed");
-                    if is_text {
-                        let text_buffer = text_from_blocks(file_buffer);
-                        let _ = filedesc.write(text_buffer.as_slice());
-                    } else {
-                        let _ = filedesc.write(file_buffer);
-                    }
-                    println!("Wrote {name} to disk");
-                    if preserve_date {
-                        let _ =
-                            filedesc.set_modified(pdate_to_systime(entry.date));
-                    }
-                    filedesc.sync_all().expect("Cannot commit to file");
-                    return;
-                }
-            }
+            pdate_to_string(pdate)
+        };
+        let date = format!("{date:width$}");
+        return if pdate_is_valid(pdate) { date } else { self.paint(&date, Color::Red) };
+    }
+
+    // An error about this image, with its name as context.
+    fn error(&self, kind: ErrorKind, message: impl Into<String>) -> DiskError {
+        return DiskError::new(kind, message).with("image", &self.image);
+    }
+
+    fn not_found(&self, name: &str) -> DiskError {
+        return self.error(ErrorKind::NotFound, format!("No file named {name} on {0}", self.image)).with("name", name);
+    }
+
+    // Why num_files can't be right, if it can't.
+    fn file_count_problem(&self) -> Option<String> {
+        let declared = self.directory.volume.num_files as usize;
+        if declared <= self.directory.entries.len() {
+            return None;
         }
+        return Some(format!("the directory says it has {declared} files, more than the {0} it has room for; only those are used",
+            self.directory.entries.len()));
     }
-    
-    pub fn change(&self, from: &str, to: &str) {
-        println!("Renaming {from} to {to} on {0}", self.image);
-        
+
+    fn num_entries(&self) -> usize {
+        return (self.directory.volume.num_files as usize).min(self.directory.entries.len());
     }
-    
-    pub fn krunch(&self) {
-        println!("Consolidating free space on {0}", self.image);
+
+    fn find_entry(&self, name: &str) -> Option<usize> {
+        return (0..self.num_entries()).find(|i| pstring_to_string(&self.directory.entries[*i].name) == name);
     }
-    
-    pub fn zero(&self) {
-        println!("Clearing directory on {0}", self.image);
+
+    // The last block the volume may use: the declared size, unless the image
+    // is too small to hold it or the size is too small to be right. Files
+    // are checked against this rather than the size of the image, which can
+    // be a larger container. A disk from directory_only has only the
+    // directory's blocks loaded, so its files all end up past this.
+    fn volume_end(&self) -> usize {
+        return self.declared_end().min(self.num_blocks());
     }
 
-    pub fn dump(&self, from: usize, to: usize) {
-        if from > to {
-            panic!("from ({from}) must be less than to ({to})");
+    // volume_end as if the whole image had been read, for catalogues of
+    // disks opened with directory_only.
+    fn declared_end(&self) -> usize {
+        let declared = self.directory.volume.num_blocks as usize;
+        if declared < self.directory_end {
+            return self.available;
         }
-        if to > self.num_blocks() {
-            panic!("to ({to}) must be less than {0} blocks", self.num_blocks());
+        return declared.min(self.available);
+    }
+
+    // Whether only the start of the image was read, by directory_only.
+    fn is_partial(&self) -> bool {
+        return self.num_blocks() < self.available;
+    }
+
+    fn check_whole(&self) -> Result<(), DiskError> {
+        if self.is_partial() {
+            return Err(self.error(ErrorKind::Usage,
+                format!("only the directory of {0} was read, so it can't be saved; run a command that reads the whole image to change it", self.image)));
         }
-        if from > self.num_blocks() {
-            panic!("from ({from}) must be less than {0} blocks", self.num_blocks());
+        return Ok(());
+    }
+
+    // Why the declared size can't be right, if it can't. A volume smaller
+    // than its image is fine, as long as the image is big enough to hold it.
+    fn size_problem(&self) -> Option<String> {
+        let declared = self.directory.volume.num_blocks as usize;
+        let available = self.available;
+        if declared < self.directory_end {
+            return Some(format!("the volume says it has {declared} blocks, which can't hold its directory; using the {available} blocks there are"));
         }
-        println!("Dumping contexts of {0} from block {1} to {2}", self.image, from, to);
-        let line_len = 16;
-        for block_no in from..=to {
-            let block = self.read_blocks(block_no, 1);
-            for line in 0..512/line_len {
-                let offset: usize = block_no * 512 + line * line_len;
-                print!("{:06x}  ", offset);
-                for byte in 0..line_len {
-                    let val = block[byte + line * line_len];
-                    print!("{:02x} ", val);
+        if declared > available {
+            return Some(format!("the volume says it has {declared} blocks, but only {available} are there; files past block {available} can't be read"));
+        }
+        return None;
+    }
+
+    pub fn free_regions(&self) -> Vec<Range<usize>> {
+        let mut used: Vec<Range<usize>> = (0..self.num_entries())
+            .filter_map(|i| self.directory.entries[i].block_range(self.volume_end()))
+            .collect();
+        used.sort_by_key(|r| r.start);
+        let mut regions = Vec::new();
+        let mut next = self.directory_end;
+        for range in used {
+            if range.start > next {
+                regions.push(next..range.start);
+            }
+            next = next.max(range.end);
+        }
+        if next < self.volume_end() {
+            regions.push(next..self.volume_end());
+        }
+        return regions;
+    }
+
+    // The volume header's raw fields are only shown with verbose; without it
+    // they're only mentioned when they're wrong.
+    pub fn list(&self, offset: usize, limit: Option<usize>, kind: Option<FileKind>, short: bool, checksum: bool, verbose: bool) -> Result<(), DiskError> {
+        println!("Listing files on {0}", self.image);
+        if short {
+            self.list_short(offset, limit, kind, checksum);
+            return Ok(());
+        }
+        let volume = &self.directory.volume;
+        if verbose {
+            println!("First block (should be 0): {}", volume.first_system_block);
+            println!("First block after directory (should be 6): {}", volume.first_block_after_directory);
+            println!("File type (should be 0): {}", volume.file_type);
+        } else {
+            if volume.first_system_block != 0 {
+                println!("{}", self.warning(&format!("first block is {}, not 0", volume.first_system_block)));
+            }
+            if let Some(problem) = self.directory_start_problem() {
+                println!("{}", self.warning(&problem));
+            }
+            if volume.file_type != 0 {
+                println!("{}", self.warning(&format!("volume file type is {}, not 0", volume.file_type)));
+            }
+        }
+        let volume_name = pstring_to_string(&self.directory.volume.volume_name);
+        println!("Volume name:      {}", volume_name);
+        if self.directory.volume.volume_name[0] as usize > VOLUME_NAME_MAX {
+            println!("{}", self.warning(&format!("volume name length {} is more than {VOLUME_NAME_MAX}, truncated", self.directory.volume.volume_name[0])));
+        } else if let Err(message) = check_volume_name(&volume_name) {
+            println!("{}", self.warning(&message));
+        }
+        println!("Number of blocks: {}", self.directory.volume.num_blocks);
+        println!("Number of files:  {}", self.directory.volume.num_files);
+        if let Some(problem) = self.file_count_problem() {
+            println!("{}", self.warning(&problem));
+        }
+        println!("Date:             {}", self.date(self.directory.volume.date, 0));
+        if verbose {
+            println!("Last access time: {}", self.directory.volume.last_access_time);
+            println!("Reserved:         {:?}", self.directory.volume.reserved);
+        }
+        let sum_heading = if checksum { " Sum " } else { "" };
+        println!("{:>5} {:15} {:10} {:>5} {:>5} {:>6} {:>7} {:>4}{sum_heading} Date",
+            "Entry", "Name", "Type", "First", "After", "Blocks", "Bytes", "Last");
+        for row in self.list_rows(offset, limit, kind, checksum) {
+            let entry = &self.directory.entries[row.index];
+            let file_type = self.paint(&format!("{:>2} {:7}", entry.file_type, row.type_name), row.type_color);
+            println!("{:>5} {:15} {file_type} {:>5} {:>5} {:>6} {:>7} {:>4}{} {}", row.index, row.name,
+                entry.first_block, entry.first_after_block, row.blocks, row.bytes, entry.bytes_in_last_block,
+                row.sum, self.date(entry.date, 0));
+            if let Some(problem) = entry.last_block_problem() {
+                println!("  {}", self.warning(&problem));
+            }
+            if row.range.is_none() {
+                println!("  {}", self.warning(&format!("block range {}..{} is not readable", entry.first_block, entry.first_after_block)));
+            }
+        }
+        self.list_system_files();
+        return Ok(());
+    }
+
+    // Just the number of files list would show, for scripts. None is a
+    // NotFound error, so the exit status says whether there were any.
+    pub fn count_files(&self, offset: usize, limit: Option<usize>, kind: Option<FileKind>) -> Result<(), DiskError> {
+        let count = self.listed_entries(offset, limit, kind).len();
+        println!("{count}");
+        if count == 0 {
+            return Err(self.error(ErrorKind::NotFound, format!("No matching files on {0}", self.image)));
+        }
+        return Ok(());
+    }
+
+    fn listed_entries(&self, offset: usize, limit: Option<usize>, kind: Option<FileKind>) -> Vec<usize> {
+        return (0..self.num_entries())
+            .filter(|i| kind.is_none() || FileKind::from_type(self.directory.entries[*i].file_type) == kind)
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .collect();
+    }
+
+    // One line per file, like the Filer's extended listing.
+    fn list_short(&self, offset: usize, limit: Option<usize>, kind: Option<FileKind>, checksum: bool) {
+        let volume = &self.directory.volume;
+        println!("{0}: {1} blocks, {2} files, {3}", pstring_to_string(&volume.volume_name),
+            volume.num_blocks, volume.num_files, self.date(volume.date, 0));
+        let sum_heading = if checksum { " Sum " } else { "" };
+        if let Some(problem) = self.file_count_problem() {
+            println!("{}", self.warning(&problem));
+        }
+        println!("{:15} {:7} {:>6} {:>7}{sum_heading} Date", "Name", "Type", "Blocks", "Bytes");
+        for row in self.list_rows(offset, limit, kind, checksum) {
+            let entry = &self.directory.entries[row.index];
+            let type_name = self.paint(&format!("{:7}", row.type_name), row.type_color);
+            match row.range {
+                Some(_) => println!("{:15} {type_name} {:>6} {:>7}{} {}", row.name, row.blocks, row.bytes, row.sum,
+                    self.date(entry.date, 0)),
+                None => println!("{:15} {type_name} {:>6} {:>7}{} {} {}", row.name, row.blocks, row.bytes, row.sum,
+                    self.date(entry.date, 10),
+                    self.paint(&format!("blocks {}..{} not readable", entry.first_block, entry.first_after_block), Color::Red)),
+            }
+            if let Some(problem) = entry.last_block_problem() {
+                println!("  {}", self.warning(&problem));
+            }
+        }
+    }
+
+    // What both listings show of each entry, already formatted for their
+    // columns: "?" for the sizes of a file whose blocks can't be read.
+    fn list_rows(&self, offset: usize, limit: Option<usize>, kind: Option<FileKind>, checksum: bool) -> Vec<ListRow> {
+        return self.listed_entries(offset, limit, kind).into_iter().map(|index| {
+            let entry = &self.directory.entries[index];
+            let range = entry.block_range(self.volume_end());
+            let (type_name, type_color) = match FileKind::from_type(entry.file_type) {
+                Some(k) => (k.short_name(), k.color()),
+                None => ("unknown", Color::Red),
+            };
+            let sum = match (&range, checksum) {
+                (_, false) => String::new(),
+                (Some(range), true) => format!(" {:04x}", self.checksum(range.clone())),
+                (None, true) => "    ?".to_string(),
+            };
+            return ListRow {
+                index,
+                name: pstring_to_string(&entry.name),
+                type_name,
+                type_color,
+                blocks: range.as_ref().map_or("?".to_string(), |r| r.len().to_string()),
+                bytes: range.as_ref().map_or("?".to_string(), |r| entry.byte_length(r).to_string()),
+                sum,
+                range,
+            };
+        }).collect();
+    }
+
+    // The files grouped by type, each group with its file count and blocks,
+    // then the totals. Groups come in the order of their type numbers, with
+    // any unknown types last, and files by name within a group. Files whose
+    // blocks can't be read are shown but not counted in the blocks.
+    pub fn tree(&self) -> Result<(), DiskError> {
+        let volume = &self.directory.volume;
+        println!("{0}: {1} blocks, {2} files", pstring_to_string(&volume.volume_name), volume.num_blocks, volume.num_files);
+        let mut groups: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+        for index in 0..self.num_entries() {
+            let key = FileKind::from_type(self.directory.entries[index].file_type).map_or(u16::MAX, |k| k.file_type());
+            groups.entry(key).or_default().push(index);
+        }
+        let blocks = |index: &usize| self.directory.entries[*index].block_range(self.volume_end()).map(|r| r.len());
+        let (mut total_files, mut total_blocks) = (0, 0);
+        for (key, mut indexes) in groups {
+            indexes.sort_by_key(|i| pstring_to_string(&self.directory.entries[*i].name));
+            let group_blocks: usize = indexes.iter().filter_map(blocks).sum();
+            let heading = match FileKind::from_type(key) {
+                Some(k) => self.paint(k.name(), k.color()),
+                None => self.paint("unknown", Color::Red),
+            };
+            println!("{heading} ({0} file(s), {group_blocks} blocks)", indexes.len());
+            for index in &indexes {
+                let name = pstring_to_string(&self.directory.entries[*index].name);
+                match blocks(index) {
+                    Some(count) => println!("  {name:15} {count:>6}"),
+                    None => println!("  {name:15} {:>6} {}", "?", self.paint("not readable", Color::Red)),
                 }
-                print!("  |");
-                for byte in 0..line_len {
-                    let mut c = block[byte + line * line_len];
-                    if c < 32 || c > 126 {
-                        c = 46;
+            }
+            total_files += indexes.len();
+            total_blocks += group_blocks;
+        }
+        println!("Total: {total_files} file(s), {total_blocks} blocks");
+        return Ok(());
+    }
+
+    // A 16-bit sum of every byte in the blocks: enough to see at a glance
+    // that two copies of a file differ, not to prove they're the same.
+    fn checksum(&self, range: Range<usize>) -> u16 {
+        return self.read_blocks(range.start, range.len()).iter()
+            .fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+    }
+
+    // Note which of the well-known system files are present, and whether the
+    // disk looks like it's meant to boot.
+    fn list_system_files(&self) {
+        let present: Vec<&(&str, &str)> = SYSTEM_FILES.iter()
+            .filter(|(name, _)| self.find_entry(name).is_some())
+            .collect();
+        let has_boot_blocks = self.read_blocks(0, 2).iter().any(|b| *b != 0);
+        if present.is_empty() && !has_boot_blocks {
+            println!("No system files or boot blocks: this is a data disk");
+            return;
+        }
+        if present.is_empty() {
+            println!("System files: none, but blocks 0-1 hold boot code");
+        } else {
+            println!("System files:");
+        }
+        for (name, role) in &present {
+            println!("  {name:16} {role}");
+        }
+        if self.find_entry("SYSTEM.PASCAL").is_some() {
+            println!("This is a system disk");
+            match self.system_version() {
+                Ok(version) => println!("  SYSTEM.PASCAL is UCSD p-system {version}"),
+                Err(message) => println!("  Can't tell the p-system version: {message}"),
+            }
+        } else {
+            println!("Warning: this looks like a system disk, but SYSTEM.PASCAL is missing so it won't boot");
+        }
+    }
+    
+    // Which p-system the disk boots, from the version its operating system's
+    // segments were compiled for.
+    fn system_version(&self) -> Result<String, String> {
+        let index = self.find_entry("SYSTEM.PASCAL").ok_or("SYSTEM.PASCAL is missing")?;
+        let range = self.directory.entries[index].block_range(self.volume_end()).ok_or("SYSTEM.PASCAL's blocks are not readable")?;
+        let codefile = Codefile::from_bytes("SYSTEM.PASCAL", self.read_blocks(range.start, range.len()).to_vec(), ByteOrder::Auto)?;
+        let mut versions: Vec<u16> = codefile.segments().iter().filter(|s| !s.is_empty()).map(|s| s.version()).collect();
+        versions.sort();
+        versions.dedup();
+        let names: Vec<String> = versions.iter().map(|v| match version_name(*v) {
+            Some(name) => name.to_string(),
+            None => format!("unknown (version {v})"),
+        }).collect();
+        return match names.len() {
+            0 => Err("SYSTEM.PASCAL has no segments".to_string()),
+            1 => Ok(names[0].clone()),
+            _ => Ok(format!("{0}, though its segments disagree", names.join(" or "))),
+        };
+    }
+
+    // A CRC-32 of the boot blocks, and with system of SYSTEM.PASCAL too, to
+    // tell which release a boot disk came from. Copies of a release share
+    // their boot code, whatever files were added to the disk since.
+    pub fn boot_signature(&self, system: bool) -> Result<(), DiskError> {
+        let boot = self.read_blocks(0, 2);
+        let signature = archive::crc32(boot);
+        if boot.iter().all(|b| *b == 0) {
+            println!("Boot blocks 0-1: all zeroes, so there's no boot code");
+        } else {
+            println!("Boot blocks 0-1: {signature:08x}");
+        }
+        if system {
+            let Some(index) = self.find_entry("SYSTEM.PASCAL") else {
+                return Err(self.not_found("SYSTEM.PASCAL"));
+            };
+            let entry = &self.directory.entries[index];
+            let Some(range) = entry.block_range(self.volume_end()) else {
+                return Err(self.error(ErrorKind::CorruptImage, format!("SYSTEM.PASCAL: block range {}..{} is not readable on a {}-block volume",
+                    entry.first_block, entry.first_after_block, self.volume_end())).with("name", "SYSTEM.PASCAL"));
+            };
+            let length = entry.byte_length(&range);
+            let contents = &self.read_blocks(range.start, range.len())[..length];
+            println!("SYSTEM.PASCAL ({length} bytes): {0:08x}", archive::crc32(contents));
+        }
+        match KNOWN_BOOT_SIGNATURES.iter().find(|(known, _)| *known == signature) {
+            Some((_, release)) => println!("Matches {release}"),
+            None => println!("No known release has these boot blocks"),
+        }
+        return Ok(());
+    }
+
+    // An infofile, with the segments of the codefile it goes with so its
+    // contents can be matched up by hand.
+    pub fn info_file(&self, name: &str) -> Result<(), DiskError> {
+        let Some(index) = self.find_entry(name) else {
+            return Err(self.not_found(name));
+        };
+        let entry = &self.directory.entries[index];
+        if entry.file_type & 0x0f != INFO_FILE {
+            let kind = FileKind::from_type(entry.file_type).map_or("unknown", |k| k.name());
+            return Err(self.error(ErrorKind::Usage, format!("{name} is a {kind} file, not an infofile")).with("name", name));
+        }
+        let Some(range) = entry.block_range(self.volume_end()) else {
+            return Err(self.error(ErrorKind::CorruptImage, format!("{name}: block range {}..{} is not readable on a {}-block volume",
+                entry.first_block, entry.first_after_block, self.volume_end())).with("name", name));
+        };
+        let length = entry.byte_length(&range);
+        println!("{name}: debugger information, {length} bytes in {0} block(s), {1}", range.len(), self.date(entry.date, 0));
+        let code_name = format!("{0}.CODE", name.strip_suffix(".INFO").unwrap_or(name));
+        let code = self.find_entry(&code_name).and_then(|i| self.directory.entries[i].block_range(self.volume_end()));
+        match code.map(|r| Codefile::from_bytes(&code_name, self.read_blocks(r.start, r.len()).to_vec(), ByteOrder::Auto)) {
+            Some(Ok(codefile)) => {
+                println!("For {code_name}, whose segments are:");
+                for segment in codefile.segments().iter().filter(|s| !s.is_empty()) {
+                    println!("  {0:2} {1:8} {2:?}", segment.index, segment.name, segment.kind);
+                }
+            }
+            Some(Err(message)) => println!("{}", self.warning(&format!("{code_name} can't be read as a codefile: {message}"))),
+            None => println!("No readable {code_name} on {0} to pair it with", self.image),
+        }
+        println!("The layout of infofiles isn't documented, so here are its bytes:");
+        let contents = &self.read_blocks(range.start, range.len())[..length];
+        for (block_no, block) in contents.chunks(BLOCK_SIZE).enumerate() {
+            print_block(block_no, block, Encoding::Ascii, self.color);
+        }
+        return Ok(());
+    }
+
+    pub fn remove(&mut self, name: &str, zero_fill: bool, dry_run: bool) -> Result<(), DiskError> {
+        println!("Removing {name} on {0}", self.image);
+        let Some(index) = self.find_entry(name) else {
+            return Err(self.not_found(name));
+        };
+        return self.remove_entry(index, zero_fill, dry_run);
+    }
+
+    // Remove the file in a directory slot, for names that can't be looked up.
+    pub fn remove_index(&mut self, index: usize, zero_fill: bool, dry_run: bool) -> Result<(), DiskError> {
+        println!("Removing entry {index} on {0}", self.image);
+        self.entry_at(index)?;
+        return self.remove_entry(index, zero_fill, dry_run);
+    }
+
+    // With zero_fill the file's blocks are cleared as well, so the whole
+    // image is saved, not just the directory.
+    fn remove_entry(&mut self, index: usize, zero_fill: bool, dry_run: bool) -> Result<(), DiskError> {
+        let entry = self.directory.entries[index];
+        let name = pstring_to_string(&entry.name);
+        println!("  Entry {index}: {name} will be removed, freeing blocks {}..{}", entry.first_block, entry.first_after_block);
+        if zero_fill {
+            let range = self.zero_fill_range(index)?;
+            println!("  Blocks {0}..{1} will be overwritten with zeros", range.start, range.end);
+            let start = (self.base + range.start) * BLOCK_SIZE;
+            self.blocks[start..start + range.len() * BLOCK_SIZE].fill(0);
+        }
+        let count = self.num_entries();
+        self.directory.entries.copy_within(index + 1..count, index);
+        self.directory.entries[count - 1] = DirectoryEntry::empty();
+        self.directory.volume.num_files = count as u16 - 1;
+        if zero_fill {
+            return self.commit(dry_run);
+        }
+        return self.commit_directory(dry_run);
+    }
+
+    // The blocks remove can clear. A damaged entry might claim the
+    // directory or another file's blocks, and clearing those would lose
+    // more than the file, so that's refused rather than trimmed.
+    fn zero_fill_range(&self, index: usize) -> Result<Range<usize>, DiskError> {
+        let entry = &self.directory.entries[index];
+        let name = pstring_to_string(&entry.name);
+        let Some(range) = entry.block_range(self.volume_end()) else {
+            return Err(self.error(ErrorKind::CorruptImage, format!("{name}: block range {}..{} is not readable on a {}-block volume, so it can't be zero-filled",
+                entry.first_block, entry.first_after_block, self.volume_end())).with("name", &name));
+        };
+        if range.start < self.directory_end {
+            return Err(self.error(ErrorKind::CorruptImage,
+                format!("{name}: blocks {0}..{1} include the directory, not zero-filling them", range.start, range.end)).with("name", &name));
+        }
+        for (other, e) in self.directory.entries[..self.num_entries()].iter().enumerate() {
+            if other != index && e.block_range(self.volume_end()).is_some_and(|r| r.start < range.end && range.start < r.end) {
+                return Err(self.error(ErrorKind::CorruptImage,
+                    format!("{name}: blocks {0}..{1} overlap {2}, not zero-filling them", range.start, range.end, pstring_to_string(&e.name)))
+                    .with("name", &name));
+            }
+        }
+        return Ok(range);
+    }
+
+    // Where a file's data is in the de-interleaved image, for tools that want
+    // to seek to it directly. The offset counts from the start of the image,
+    // not the Pascal volume, so it includes any --offset or --prodos-path.
+    pub fn locate(&self, name: &str) -> Result<(), DiskError> {
+        let Some(index) = self.find_entry(name) else {
+            return Err(self.not_found(name));
+        };
+        let entry = self.directory.entries[index];
+        let Some(range) = entry.block_range(self.volume_end()) else {
+            return Err(self.error(ErrorKind::CorruptImage, format!("{name}: block range {}..{} is not readable on a {}-block volume",
+                entry.first_block, entry.first_after_block, self.volume_end())).with("name", name));
+        };
+        if let Some(problem) = entry.last_block_problem() {
+            eprintln!("Warning: {problem}");
+        }
+        let offset = (self.base + range.start) * BLOCK_SIZE;
+        println!("{name}: offset {offset} ({offset:#x}), length {0} bytes, blocks {1}..{2}",
+            entry.byte_length(&range), range.start, range.end);
+        return Ok(());
+    }
+
+    // Decode every readable textfile into one document, each headed by its
+    // name, for reading through a disk's source in one go.
+    pub fn export_text(&self, out: &str, order: FileOrder, force: bool) -> Result<(), DiskError> {
+        println!("Exporting the textfiles on {0} to {out}", self.image);
+        if !force && Path::new(out).exists() {
+            return Err(self.error(ErrorKind::Refused,
+                format!("{out} already exists, refusing to overwrite it without --force")).with("path", out));
+        }
+        let mut entries: Vec<&DirectoryEntry> = self.directory.entries[..self.num_entries()].iter()
+            .filter(|e| FileKind::from_type(e.file_type) == Some(FileKind::Text))
+            .collect();
+        if order == FileOrder::Name {
+            entries.sort_by_key(|e| pstring_to_string(&e.name));
+        }
+        let mut document = Vec::new();
+        let mut count = 0;
+        for entry in entries {
+            let name = pstring_to_string(&entry.name);
+            let Some(range) = entry.block_range(self.volume_end()) else {
+                eprintln!("Warning: skipping {name}: block range {}..{} is not readable on a {}-block volume",
+                    entry.first_block, entry.first_after_block, self.volume_end());
+                continue;
+            };
+            let mut text = text_from_blocks(self.read_blocks(range.start, range.len()), false, self.text_page_size);
+            if !text.is_empty() && !text.ends_with(b"\n") {
+                text.push(b'\n');
+            }
+            if count > 0 {
+                document.push(b'\n');
+            }
+            document.extend(format!("===== {name} =====\n").into_bytes());
+            document.append(&mut text);
+            println!("  {name}");
+            count += 1;
+        }
+        fs::write(out, document).map_err(|e| DiskError::from(e).with("path", out))?;
+        println!("Wrote {count} textfile(s) to {out}");
+        return Ok(());
+    }
+
+    // Write the volume on its own to a new image, in the sector order its
+    // name calls for, so a volume found with --offset or --prodos-path can
+    // be used by tools that expect a plain disk image.
+    pub fn extract_volume(&self, out: &str, force: bool) -> Result<(), DiskError> {
+        let count = self.volume_end();
+        println!("Extracting the {count}-block volume at block {0} of {1} into {out}", self.base, self.image);
+        if Path::new(out) == Path::new(&self.image) {
+            return Err(self.error(ErrorKind::Refused, format!("{out} is the image being read")).with("path", out));
+        }
+        if !force && Path::new(out).exists() {
+            return Err(self.error(ErrorKind::Refused,
+                format!("{out} already exists, refusing to overwrite it without --force")).with("path", out));
+        }
+        let layout = Layout::standard(out);
+        let mut blocks = self.read_blocks(0, count).to_vec();
+        if let Some(track_len) = layout.track_len() && !blocks.len().is_multiple_of(track_len) {
+            blocks.resize(blocks.len().next_multiple_of(track_len), 0);
+            println!("  {out} is DOS-ordered, so it's padded to {0} blocks to make whole tracks", blocks.len() / BLOCK_SIZE);
+        }
+        let contents = layout.interleave(&blocks);
+        fs::write(out, contents).map_err(|e| DiskError::from(e).with("path", out))?;
+        println!("Wrote {out}");
+        return Ok(());
+    }
+
+    // Copy every readable file into dir, with a manifest.json that maps each
+    // Pascal name to the host file it was written to, so the originals can
+    // be recovered even when the host changes or folds the names.
+    pub fn extract_all(&self, dir: &str, is_text: bool) -> Result<(), DiskError> {
+        println!("Extracting files from {0} into {dir}", self.image);
+        fs::create_dir_all(dir).map_err(|e| DiskError::from(e).with("path", dir))?;
+        let mut used = HashSet::from(["manifest.json".to_string()]);
+        let mut manifest = Vec::new();
+        for entry in &self.directory.entries[..self.num_entries()] {
+            let name = pstring_to_string(&entry.name);
+            let Some(range) = entry.block_range(self.volume_end()) else {
+                eprintln!("Warning: skipping {name}: block range {}..{} is not readable on a {}-block volume",
+                    entry.first_block, entry.first_after_block, self.volume_end());
+                continue;
+            };
+            if let Some(problem) = entry.last_block_problem() {
+                eprintln!("Warning: {problem}");
+            }
+            let host_name = host_file_name(&name, &mut used);
+            let file_buffer = self.read_blocks(range.start, range.len());
+            let decode = is_text && FileKind::from_type(entry.file_type) == Some(FileKind::Text);
+            let contents = if decode {
+                text_from_blocks(file_buffer, false, self.text_page_size)
+            } else {
+                file_buffer[..entry.byte_length(&range)].to_vec()
+            };
+            let path = Path::new(dir).join(&host_name);
+            fs::write(&path, contents).map_err(|e| DiskError::from(e).with("path", path.display()))?;
+            if host_name == name {
+                println!("  {name}");
+            } else {
+                println!("  {name} -> {host_name}");
+            }
+            manifest.push(format!("    {{\"name\": {0}, \"file\": {1}, \"type\": {2}, \"date\": {3}, \"text\": {4}}}",
+                json_string(&name), json_string(&host_name), entry.file_type, json_string(&pdate_to_string(entry.date)), decode));
+        }
+        let volume = pstring_to_string(&self.directory.volume.volume_name);
+        let json = format!("{{\n  \"volume\": {0},\n  \"files\": [\n{1}\n  ]\n}}\n", json_string(&volume), manifest.join(",\n"));
+        let path = Path::new(dir).join("manifest.json");
+        fs::write(&path, json).map_err(|e| DiskError::from(e).with("path", path.display()))?;
+        println!("Extracted {0} file(s) and manifest.json", manifest.len());
+        return Ok(());
+    }
+
+    pub fn transfer(&mut self, name: &str, to_image: bool, options: &TransferOptions, dry_run: bool) -> Result<(), DiskError> {
+        if to_image {
+            return self.transfer_to_image(name, options, dry_run);
+        } else {
+            println!("Copying {name} from {0}", self.image);
+            let mut readable = Vec::new();
+            for index in 0..self.num_entries() {
+                let entry = &self.directory.entries[index];
+                if pstring_to_string(&entry.name) != name {
+                    continue;
+                }
+                println!("Found {name} at block {0}", entry.first_block);
+                match entry.block_range(self.volume_end()) {
+                    Some(range) => readable.push((index, range)),
+                    None => eprintln!("Warning: skipping {name}: block range {}..{} is not readable on a {}-block volume",
+                        entry.first_block, entry.first_after_block, self.volume_end()),
+                }
+            }
+            // A damaged directory can name two files the same. Copying the
+            // first would quietly hide the others, so the slot has to be
+            // picked.
+            return match readable.as_slice() {
+                [] => Err(self.error(ErrorKind::NotFound, format!("No readable file named {name} on {0}", self.image)).with("name", name)),
+                [(index, range)] => self.copy_to_host(&self.directory.entries[*index], range.clone(), name, options),
+                _ => {
+                    let slots: Vec<String> = readable.iter().map(|(index, _)| index.to_string()).collect();
+                    Err(self.error(ErrorKind::Usage,
+                        format!("{0} files are named {name}, in entries {1}; use --index to pick one", readable.len(), slots.join(", ")))
+                        .with("name", name))
+                }
+            };
+        }
+    }
+
+    // Copy the file in a directory slot, whatever its name says. The host
+    // file is named after it, made safe, since a damaged name can hold
+    // anything.
+    pub fn transfer_index(&self, index: usize, options: &TransferOptions) -> Result<(), DiskError> {
+        let entry = self.entry_at(index)?;
+        let name = pstring_to_string(&entry.name);
+        println!("Copying entry {index} ({name}) from {0}", self.image);
+        let Some(range) = entry.block_range(self.volume_end()) else {
+            return Err(self.error(ErrorKind::CorruptImage, format!("entry {index}: block range {}..{} is not readable on a {}-block volume",
+                entry.first_block, entry.first_after_block, self.volume_end())).with("index", index));
+        };
+        let file_name = host_file_name(&name, &mut HashSet::new());
+        return self.copy_to_host(entry, range, &file_name, options);
+    }
+
+    // Copy each file named in a list, one name or entry number a line, as
+    // transfer or transfer_index would. A line of only digits is an entry
+    // number. One that can't be copied is reported and the rest still are;
+    // the error at the end is for scripts, which can't read the summary.
+    pub fn transfer_list(&mut self, list: &str, options: &TransferOptions) -> Result<(), DiskError> {
+        let text = fs::read_to_string(list).map_err(|e| DiskError::from(e).with("path", list))?;
+        let lines: Vec<&str> = text.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+        let mut missing = Vec::new();
+        for line in &lines {
+            let result = match line.parse::<usize>() {
+                Ok(index) => self.transfer_index(index, options),
+                Err(_) => self.transfer(line, false, options, false),
+            };
+            if let Err(e) = result {
+                eprintln!("Warning: {line}: {e}");
+                missing.push(*line);
+            }
+        }
+        println!("Copied {0} of {1} file(s) listed in {list}", lines.len() - missing.len(), lines.len());
+        if !missing.is_empty() {
+            println!("Not copied: {0}", missing.join(", "));
+            return Err(self.error(ErrorKind::NotFound, format!("{0} of the files listed in {list} weren't copied", missing.len()))
+                .with("path", list));
+        }
+        return Ok(());
+    }
+
+    fn entry_at(&self, index: usize) -> Result<&DirectoryEntry, DiskError> {
+        if index >= self.num_entries() {
+            return Err(self.error(ErrorKind::NotFound, format!("No entry {index}, the directory has {0} file(s)", self.num_entries()))
+                .with("index", index));
+        }
+        return Ok(&self.directory.entries[index]);
+    }
+
+    fn copy_to_host(&self, entry: &DirectoryEntry, range: Range<usize>, file_name: &str, options: &TransferOptions) -> Result<(), DiskError> {
+        let file_buffer = self.read_blocks(range.start, range.len());
+        if options.interface {
+            return self.copy_interface_to_host(entry, file_buffer, file_name, options);
+        }
+        if options.convert_foto {
+            return self.copy_foto_to_host(entry, range, file_name, options);
+        }
+        if !options.force && Path::new(file_name).exists() {
+            return Err(self.error(ErrorKind::Refused,
+                format!("{file_name} already exists, refusing to overwrite it without --force")).with("path", file_name));
+        }
+        // Because we want to possibly use set_times, we'll
+        // have to use more conventional File:: methods.
+        let mut filedesc = File::create(file_name).map_err(|e| DiskError::from(e).with("path", file_name))?;
+        if options.as_blocks && FileKind::from_type(entry.file_type) != Some(FileKind::Text) {
+            println!("  Warning: {file_name} is not a text file, copying its blocks anyway");
+        }
+        if options.is_text {
+            let text_buffer = text_from_blocks(file_buffer, options.keep_cr, self.text_page_size);
+            let _ = filedesc.write(text_buffer.as_slice());
+        } else {
+            let _ = filedesc.write(file_buffer);
+        }
+        println!("Wrote {file_name} to disk");
+        if options.preserve_date {
+            let _ =
+                filedesc.set_modified(pdate_to_systime(entry.date));
+        }
+        filedesc.sync_all().map_err(|e| DiskError::from(e).with("path", file_name))?;
+        return Ok(());
+    }
+
+    // A unit's codefile keeps the text of its INTERFACE section, in textfile
+    // pages just before the unit's code, so it can be recovered without the
+    // source. It's written to the file's name with .TEXT in place of .CODE.
+    fn copy_interface_to_host(&self, entry: &DirectoryEntry, file_buffer: &[u8], file_name: &str, options: &TransferOptions) -> Result<(), DiskError> {
+        if FileKind::from_type(entry.file_type) != Some(FileKind::Code) {
+            return Err(self.error(ErrorKind::Usage, format!("{file_name} is not a code file, so it has no interface text")).with("name", file_name));
+        }
+        let codefile = Codefile::from_bytes(file_name, file_buffer.to_vec(), ByteOrder::Auto)
+            .map_err(|message| self.error(ErrorKind::CorruptImage, message).with("name", file_name))?;
+        let mut text = String::new();
+        for segment in codefile.segments() {
+            if segment.kind != SegmentKind::UnitSegment {
+                continue;
+            }
+            let unit = segment.name.trim();
+            match codefile.interface_text(segment.index) {
+                Ok(interface) => {
+                    println!("Found the interface of unit {unit} at block {0} of {file_name}", segment.text_addr);
+                    text.push_str(&interface);
+                }
+                Err(message) => println!("  Warning: unit {unit}: {message}"),
+            }
+        }
+        if text.is_empty() {
+            return Err(self.error(ErrorKind::NotFound, format!("{file_name} has no unit segments with interface text")).with("name", file_name));
+        }
+        let stem = file_name.strip_suffix(".CODE").unwrap_or(file_name);
+        let text_name = format!("{stem}.TEXT");
+        if !options.force && Path::new(&text_name).exists() {
+            return Err(self.error(ErrorKind::Refused,
+                format!("{text_name} already exists, refusing to overwrite it without --force")).with("path", &text_name));
+        }
+        fs::write(&text_name, text).map_err(|e| DiskError::from(e).with("path", &text_name))?;
+        if options.preserve_date {
+            let file = File::options().write(true).open(&text_name).map_err(|e| DiskError::from(e).with("path", &text_name))?;
+            let _ = file.set_modified(pdate_to_systime(entry.date));
+        }
+        println!("Wrote {text_name} to disk");
+        return Ok(());
+    }
+
+    // A fotofile is a saved hi-res screen. It's written to the file's name
+    // with .png in place of .FOTO.
+    fn copy_foto_to_host(&self, entry: &DirectoryEntry, range: Range<usize>, file_name: &str, options: &TransferOptions) -> Result<(), DiskError> {
+        if FileKind::from_type(entry.file_type) != Some(FileKind::Foto) {
+            return Err(self.error(ErrorKind::Usage, format!("{file_name} is not a fotofile")).with("name", file_name));
+        }
+        let screen = &self.read_blocks(range.start, range.len())[..entry.byte_length(&range)];
+        if screen.len() != foto::SCREEN_SIZE {
+            println!("  Warning: {file_name} is {0} bytes, not the {1} of a hi-res screen", screen.len(), foto::SCREEN_SIZE);
+        }
+        let stem = file_name.strip_suffix(".FOTO").unwrap_or(file_name);
+        let png_name = format!("{stem}.png");
+        if !options.force && Path::new(&png_name).exists() {
+            return Err(self.error(ErrorKind::Refused,
+                format!("{png_name} already exists, refusing to overwrite it without --force")).with("path", &png_name));
+        }
+        fs::write(&png_name, foto::screen_to_png(screen)).map_err(|e| DiskError::from(e).with("path", &png_name))?;
+        if options.preserve_date {
+            let file = File::options().write(true).open(&png_name).map_err(|e| DiskError::from(e).with("path", &png_name))?;
+            let _ = file.set_modified(pdate_to_systime(entry.date));
+        }
+        println!("Wrote {png_name} to disk");
+        return Ok(());
+    }
+
+    // Files are placed in the first free region big enough to hold them,
+    // unless at_block picks the spot, and dated with the volume's date, as
+    // the Filer does, unless preserve_date asks for the host file's date.
+    // With as_blocks, the host file is already a textfile in the on-disk
+    // format, so it's copied unchanged and only marked as text.
+    fn transfer_to_image(&mut self, path: &str, options: &TransferOptions, dry_run: bool) -> Result<(), DiskError> {
+        println!("Copying {path} to {0}", self.image);
+        let host_name = Path::new(path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let name = host_name.to_uppercase();
+        if let Err(message) = check_file_name(&name) {
+            return Err(self.error(ErrorKind::Usage, format!("Can't copy {path}: {message}")).with("path", path));
+        }
+        self.check_new_entry(&name)?;
+        let contents = fs::read(path).map_err(|e| DiskError::from(e).with("path", path))?;
+        let data = if options.is_text { text_to_blocks(&contents, self.text_page_size) } else { contents };
+        if options.as_blocks && !data.len().is_multiple_of(self.text_page_size) {
+            println!("  Warning: {path} is not a whole number of {0}-byte text pages", self.text_page_size);
+        }
+        let file_type = if options.is_text || options.as_blocks { TEXT_FILE } else { file_type_for_name(&name) };
+        let mut date = self.directory.volume.date;
+        if options.preserve_date {
+            let modified = fs::metadata(path).and_then(|m| m.modified()).map_err(|e| DiskError::from(e).with("path", path))?;
+            match systime_to_pdate(modified) {
+                Ok(pdate) => date = pdate,
+                Err(message) => println!("  Warning: {message}, using the volume date"),
+            }
+        }
+        let entry = self.place_file(&name, &data, file_type, options.at_block, date)?;
+        println!("  {name}: {} bytes, type {file_type}, dated {}, will be written to blocks {}..{}",
+            data.len(), pdate_to_string(date), entry.first_block, entry.first_after_block);
+        return self.commit(dry_run);
+    }
+
+    // Add a file to the volume in memory, in the first free space that
+    // holds it, dated with the volume's date. With text set, data is plain
+    // text with LF or CR line endings and is written as a textfile; the
+    // file_type is used as given either way. Nothing is written to the image
+    // until save.
+    pub fn write_file(&mut self, name: &str, data: &[u8], file_type: FileKind, text: bool) -> Result<DirectoryEntry, DiskError> {
+        let name = name.to_uppercase();
+        if let Err(message) = check_file_name(&name) {
+            return Err(self.error(ErrorKind::Usage, format!("Can't write {name}: {message}")).with("name", &name));
+        }
+        self.check_new_entry(&name)?;
+        let data = if text { text_to_blocks(data, self.text_page_size) } else { data.to_vec() };
+        return self.place_file(&name, &data, file_type.file_type(), None, self.directory.volume.date);
+    }
+
+    // Whether a file of this name can be added.
+    fn check_new_entry(&self, name: &str) -> Result<(), DiskError> {
+        if self.find_entry(name).is_some() {
+            return Err(self.error(ErrorKind::Refused, format!("{name} already exists on {0}; remove it first", self.image)).with("name", name));
+        }
+        if self.num_entries() == self.directory.entries.len() {
+            return Err(self.error(ErrorKind::NoSpace, format!("The directory on {0} is full", self.image)));
+        }
+        return Ok(());
+    }
+
+    // Copy data into free blocks, at_block or the first that fit, and add
+    // its entry. The name has already been checked.
+    fn place_file(&mut self, name: &str, data: &[u8], file_type: u16, at_block: Option<usize>, date: u16) -> Result<DirectoryEntry, DiskError> {
+        let needed = data.len().div_ceil(BLOCK_SIZE).max(1);
+        let region = match at_block {
+            Some(start) => self.region_at(start, needed)?,
+            None => match self.free_regions().into_iter().find(|r| r.len() >= needed) {
+                Some(region) => region,
+                None => return Err(self.error(ErrorKind::NoSpace, format!("No free region of {needed} blocks on {0}", self.image))
+                    .with("blocks", needed)),
+            },
+        };
+        let last = data.len() % BLOCK_SIZE;
+        let entry = DirectoryEntry {
+            first_block: region.start as u16,
+            first_after_block: (region.start + needed) as u16,
+            file_type,
+            name: string_to_pstring(name),
+            bytes_in_last_block: if last == 0 && !data.is_empty() { BLOCK_SIZE as u16 } else { last as u16 },
+            date,
+        };
+        let start = (self.base + region.start) * BLOCK_SIZE;
+        let end = start + needed * BLOCK_SIZE;
+        self.blocks[start..end].fill(0);
+        self.blocks[start..start + data.len()].copy_from_slice(data);
+        // Keep the directory in block order.
+        let count = self.num_entries();
+        let index = (0..count)
+            .find(|i| self.directory.entries[*i].first_block as usize > region.start)
+            .unwrap_or(count);
+        self.directory.entries.copy_within(index..count, index + 1);
+        self.directory.entries[index] = entry;
+        self.directory.volume.num_files = count as u16 + 1;
+        return Ok(entry);
+    }
+
+    // The blocks a file would take up starting at start, if they're all free.
+    fn region_at(&self, start: usize, needed: usize) -> Result<Range<usize>, DiskError> {
+        let region = start..start + needed;
+        if region.end > self.volume_end() {
+            return Err(self.error(ErrorKind::Usage, format!("blocks {0}..{1} run past the end of the {2}-block volume",
+                region.start, region.end, self.volume_end())).with("at_block", start));
+        }
+        if region.start < self.directory_end {
+            return Err(self.error(ErrorKind::Refused, format!("blocks {0}..{1} overlap the boot blocks or directory, which end at block {2}",
+                region.start, region.end, self.directory_end)).with("at_block", start));
+        }
+        for entry in &self.directory.entries[..self.num_entries()] {
+            if let Some(used) = entry.block_range(self.volume_end()) && used.start < region.end && region.start < used.end {
+                return Err(self.error(ErrorKind::Refused, format!("blocks {0}..{1} overlap {2}, at blocks {3}..{4}",
+                    region.start, region.end, pstring_to_string(&entry.name), used.start, used.end)).with("at_block", start));
+            }
+        }
+        return Ok(region);
+    }
+
+    // A trailing colon renames the volume, as in the Filer.
+    pub fn change(&mut self, from: &str, to: &str, dry_run: bool) -> Result<(), DiskError> {
+        println!("Renaming {from} to {to} on {0}", self.image);
+        let to = to.to_uppercase();
+        if let Some(volume) = from.strip_suffix(':') {
+            let to = to.trim_end_matches(':');
+            let current = pstring_to_string(&self.directory.volume.volume_name);
+            if volume != current {
+                return Err(self.error(ErrorKind::NotFound, format!("The volume on {0} is {current}, not {volume}", self.image)));
+            }
+            if let Err(message) = check_volume_name(to) {
+                return Err(self.error(ErrorKind::Usage, format!("Can't rename volume: {message}")));
+            }
+            println!("  Volume {current} will be renamed {to}");
+            self.directory.volume.volume_name = string_to_pstring(to);
+            return self.commit_directory(dry_run);
+        }
+        let Some(index) = self.find_entry(from) else {
+            return Err(self.not_found(from));
+        };
+        if let Err(message) = check_file_name(&to) {
+            return Err(self.error(ErrorKind::Usage, format!("Can't rename {from}: {message}")));
+        }
+        if self.find_entry(&to).is_some() {
+            return Err(self.error(ErrorKind::Refused, format!("{to} already exists on {0}", self.image)).with("name", to));
+        }
+        println!("  Entry {index}: {from} will be renamed {to}");
+        self.directory.entries[index].name = string_to_pstring(&to);
+        return self.commit_directory(dry_run);
+    }
+
+    // Only the kind, in the low four bits, is changed; the bits above it are
+    // kept as they are.
+    pub fn set_type(&mut self, name: &str, kind: FileKind, dry_run: bool) -> Result<(), DiskError> {
+        println!("Setting the type of {name} on {0}", self.image);
+        let Some(index) = self.find_entry(name) else {
+            return Err(self.not_found(name));
+        };
+        let entry = &mut self.directory.entries[index];
+        let old = FileKind::from_type(entry.file_type).map_or("unknown", |k| k.name());
+        if FileKind::from_type(entry.file_type) == Some(kind) {
+            println!("  Entry {index}: {name} is already {old}, nothing to change");
+            return Ok(());
+        }
+        println!("  Entry {index}: {name} will be changed from {old} to {0}", kind.name());
+        entry.file_type = (entry.file_type & !0x0f) | kind.file_type();
+        return self.commit_directory(dry_run);
+    }
+
+    // Slide files down towards the directory so the free space ends up in
+    // one region at the end of the volume. The directory is kept in block
+    // order, so files are moved in directory order.
+    pub fn krunch(&mut self, quiet: bool, dry_run: bool) -> Result<(), DiskError> {
+        println!("Consolidating free space on {0}", self.image);
+        // Moves are planned in directory order, each file going to the end
+        // of the one before. That's only safe if the directory is in block
+        // order: otherwise a file can be moved on top of one listed later.
+        let readable: Vec<&DirectoryEntry> = self.directory.entries[..self.num_entries()].iter()
+            .filter(|e| e.block_range(self.volume_end()).is_some())
+            .collect();
+        if let Some(pair) = readable.windows(2).find(|p| p[1].first_block < p[0].first_block) {
+            return Err(self.error(ErrorKind::CorruptImage, format!(
+                "the directory isn't in block order: {0} (block {1}) is listed before {2} (block {3}), so krunch could overwrite files",
+                pstring_to_string(&pair[0].name), pair[0].first_block, pstring_to_string(&pair[1].name), pair[1].first_block)));
+        }
+        let starts_before: Vec<u16> = (0..self.num_entries()).map(|i| self.directory.entries[i].first_block).collect();
+        let free_before = self.free_regions();
+        let mut next = self.directory_end;
+        let mut moves = Vec::new();
+        for index in 0..self.num_entries() {
+            let entry = &self.directory.entries[index];
+            let name = pstring_to_string(&entry.name);
+            let Some(range) = entry.block_range(self.volume_end()) else {
+                println!("  Warning: leaving {name} alone, its block range {}..{} is not readable",
+                    entry.first_block, entry.first_after_block);
+                continue;
+            };
+            if range.start < next {
+                println!("  Warning: leaving {name} alone, it overlaps the file before it");
+                next = next.max(range.end);
+                continue;
+            }
+            if range.start > next {
+                println!("  {name}: blocks {}..{} will move to {}..{}", range.start, range.end, next, next + range.len());
+                moves.push((index, range.clone(), next));
+            }
+            next += range.len();
+        }
+        if moves.is_empty() {
+            println!("Nothing to move");
+            return Ok(());
+        }
+        for (index, range, to) in moves {
+            let from = (self.base + range.start) * BLOCK_SIZE..(self.base + range.end) * BLOCK_SIZE;
+            self.blocks.copy_within(from, (self.base + to) * BLOCK_SIZE);
+            let entry = &mut self.directory.entries[index];
+            entry.first_block = to as u16;
+            entry.first_after_block = (to + range.len()) as u16;
+        }
+        if !quiet {
+            self.compact_report(&starts_before, &free_before);
+        }
+        return self.commit(dry_run);
+    }
+
+    fn compact_report(&self, starts_before: &[u16], free_before: &[Range<usize>]) {
+        println!("Layout after krunch:");
+        println!("  {:15}  {:>6}  {:>6}", "File", "Before", "After");
+        for (index, before) in starts_before.iter().enumerate() {
+            let entry = &self.directory.entries[index];
+            let marker = if *before == entry.first_block { "" } else { "  moved" };
+            println!("  {:15}  {:>6}  {:>6}{marker}", pstring_to_string(&entry.name), before, entry.first_block);
+        }
+        let free_after = self.free_regions();
+        let summary = |regions: &[Range<usize>]| {
+            let total: usize = regions.iter().map(|r| r.len()).sum();
+            let largest = regions.iter().map(|r| r.len()).max().unwrap_or(0);
+            (largest, format!("{total} blocks in {} region(s), largest {largest}", regions.len()))
+        };
+        let (largest_before, before) = summary(free_before);
+        let (largest_after, after) = summary(&free_after);
+        println!("  Free before: {before}");
+        println!("  Free after:  {after}");
+        println!("  Reclaimed {} blocks into the largest free region", largest_after.saturating_sub(largest_before));
+    }
+
+    // Search every textfile for pattern. Pages are decoded one at a time so
+    // each match can be reported with the page it's on (counting from 1,
+    // after the header) as well as its line in the file.
+    // With count_only, only the number of matching lines is printed, and
+    // none is a NotFound error.
+    pub fn find_text(&self, pattern: &str, count_only: bool) -> Result<(), DiskError> {
+        if !count_only {
+            println!("Searching text files on {0} for \"{pattern}\"", self.image);
+        }
+        let mut matches = 0;
+        let mut files = 0;
+        for entry in &self.directory.entries[..self.num_entries()] {
+            if FileKind::from_type(entry.file_type) != Some(FileKind::Text) {
+                continue;
+            }
+            let name = pstring_to_string(&entry.name);
+            let Some(range) = entry.block_range(self.volume_end()) else {
+                eprintln!("Warning: skipping {name}: block range {}..{} is not readable on a {}-block volume",
+                    entry.first_block, entry.first_after_block, self.volume_end());
+                continue;
+            };
+            files += 1;
+            let buffer = self.read_blocks(range.start, range.len());
+            let mut line_no = 0;
+            let page_size = self.text_page_size;
+            for (page, bytes) in buffer[buffer.len().min(page_size)..].chunks(page_size).enumerate() {
+                let text = text_from_pages(bytes, false);
+                for line in String::from_utf8_lossy(&text).lines() {
+                    line_no += 1;
+                    if line.contains(pattern) {
+                        if !count_only {
+                            println!("{name}: page {0}, line {line_no}: {line}", page + 1);
+                        }
+                        matches += 1;
                     }
-                    print!("{}", char::from(c));
                 }
-                println!("|");
             }
-            println!("")
         }
+        if !count_only {
+            println!("{matches} match(es) in {files} text file(s)");
+            return Ok(());
+        }
+        println!("{matches}");
+        if matches == 0 {
+            return Err(self.error(ErrorKind::NotFound, format!("No matches for \"{pattern}\" on {0}", self.image)));
+        }
+        return Ok(());
+    }
+
+    // Empty the directory, keeping the volume's name, size and date. File
+    // data is left where it is.
+    // Unless told yes up front, ask before going ahead, since the file list
+    // can't be recovered afterwards.
+    pub fn zero(&mut self, yes: bool, dry_run: bool) -> Result<(), DiskError> {
+        println!("Clearing directory on {0}", self.image);
+        let volume_name = pstring_to_string(&self.directory.volume.volume_name);
+        println!("  {} file(s) will be removed from {volume_name}", self.num_entries());
+        if !yes && !dry_run && !confirm(&format!("Zero the directory of {volume_name}, losing {0} file(s)?", self.num_entries())) {
+            return Err(self.error(ErrorKind::Refused, "Not zeroed"));
+        }
+        self.directory.entries.fill(DirectoryEntry::empty());
+        self.directory.volume.num_files = 0;
+        return self.commit_directory(dry_run);
+    }
+
+    // Why first_block_after_directory can't be trusted, if it can't. Values
+    // past the image are already ignored when the directory is read; a
+    // directory that runs into the first file's blocks is just as wrong.
+    fn directory_start_problem(&self) -> Option<String> {
+        let value = self.directory.volume.first_block_after_directory as usize;
+        if value == DIRECTORY_END {
+            return None;
+        }
+        if self.directory_end != value {
+            return Some(format!("first block after directory is {value}, which doesn't fit the volume; expected {DIRECTORY_END}"));
+        }
+        let first_file = self.directory.entries[..self.num_entries()].iter()
+            .map(|e| e.first_block as usize)
+            .filter(|b| *b != 0)
+            .min()?;
+        if first_file < value {
+            return Some(format!("first block after directory is {value}, but a file starts at block {first_file}; expected {DIRECTORY_END}"));
+        }
+        return None;
+    }
+
+    // Check the directory for damage, without changing anything.
+    pub fn verify(&self) -> Result<(), DiskError> {
+        println!("Verifying {0}", self.image);
+        let problems = self.problems();
+        for problem in &problems {
+            println!("  {problem}");
+        }
+        if problems.is_empty() {
+            println!("No problems found");
+            return Ok(());
+        }
+        return Err(self.error(ErrorKind::CorruptImage, format!("{0} problem(s) found", problems.len())));
+    }
+
+    // Everything verify checks. Reading carries on past all of them, but
+    // strict makes any of them an error.
+    fn problems(&self) -> Vec<String> {
+        let volume = &self.directory.volume;
+        let mut problems = Vec::new();
+        if volume.first_system_block != 0 {
+            problems.push(format!("first block is {0}, not 0", volume.first_system_block));
+        }
+        problems.extend(self.directory_start_problem());
+        if volume.file_type != 0 {
+            problems.push(format!("volume file type is {0}, not 0", volume.file_type));
+        }
+        if let Err(message) = check_volume_name(&pstring_to_string(&volume.volume_name)) {
+            problems.push(message);
+        }
+        if !pdate_is_valid(volume.date) {
+            problems.push(format!("volume date {0} ({1:#06x}) is not a real date", pdate_to_string(volume.date), volume.date));
+        }
+        problems.extend(self.size_problem());
+        problems.extend(self.file_count_problem());
+        for entry in &self.directory.entries[..self.num_entries()] {
+            let name = pstring_to_string(&entry.name);
+            if entry.block_range(self.volume_end()).is_none() {
+                problems.push(format!("{name}: block range {0}..{1} is not readable on a {2}-block volume",
+                    entry.first_block, entry.first_after_block, self.volume_end()));
+            }
+            problems.extend(entry.last_block_problem());
+            if FileKind::from_type(entry.file_type).is_none() {
+                problems.push(format!("{name}: file type {0} is not a known kind", entry.file_type));
+            }
+            if let Err(message) = check_file_name(&name) {
+                problems.push(message);
+            }
+            if !pdate_is_valid(entry.date) {
+                problems.push(format!("{name}: date {0} ({1:#06x}) is not a real date", pdate_to_string(entry.date), entry.date));
+            }
+        }
+        problems.extend(self.overlap_problems());
+        return problems;
+    }
+
+    // With --strict, an image is only worked on if verify would pass it.
+    pub fn check_strict(&self) -> Result<(), DiskError> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        for problem in &problems {
+            eprintln!("  {problem}");
+        }
+        return Err(self.error(ErrorKind::CorruptImage, format!("{0} problem(s) found, and --strict is on", problems.len())));
+    }
+
+    // Files whose readable blocks are shared with a file earlier in the
+    // directory.
+    fn overlap_problems(&self) -> Vec<String> {
+        let entries = &self.directory.entries[..self.num_entries()];
+        let mut problems = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let Some(range) = entry.block_range(self.volume_end()) else {
+                continue;
+            };
+            for earlier in &entries[..i] {
+                if earlier.block_range(self.volume_end()).is_some_and(|r| r.start < range.end && range.start < r.end) {
+                    problems.push(format!("{0}: blocks {1}..{2} overlap {3}, at {4}..{5}", pstring_to_string(&entry.name),
+                        range.start, range.end, pstring_to_string(&earlier.name), earlier.first_block, earlier.first_after_block));
+                }
+            }
+        }
+        return problems;
+    }
+
+    // Repair the entries verify finds damaged, so the good files can be read
+    // again. A file that runs past the end of the volume is cut short there.
+    // One that's empty, reversed, starts in the directory or past the end, or
+    // shares blocks with a file kept before it, is removed, since there's no
+    // telling where its data really is.
+    pub fn fix_entries(&mut self, yes: bool, dry_run: bool) -> Result<(), DiskError> {
+        println!("Checking the entries on {0}", self.image);
+        let end = self.volume_end();
+        let mut kept: Vec<DirectoryEntry> = Vec::new();
+        let mut repairs = Vec::new();
+        for entry in &self.directory.entries[..self.num_entries()] {
+            let mut entry = *entry;
+            let name = pstring_to_string(&entry.name);
+            let (first, after) = (entry.first_block as usize, entry.first_after_block as usize);
+            if after <= first || first < self.directory_end || first >= end {
+                repairs.push(format!("{name}: block range {first}..{after} can't hold a file, the entry will be removed"));
+                continue;
+            }
+            let last = after.min(end);
+            if let Some(other) = kept.iter().find(|k| first < k.first_after_block as usize && (k.first_block as usize) < last) {
+                repairs.push(format!("{name}: blocks {first}..{last} overlap {0}, the entry will be removed", pstring_to_string(&other.name)));
+                continue;
+            }
+            if after > end {
+                repairs.push(format!("{name}: blocks {first}..{after} run past the end of the {end}-block volume, it will be cut to {first}..{end}"));
+                entry.first_after_block = end as u16;
+                entry.bytes_in_last_block = BLOCK_SIZE as u16;
+            }
+            if entry.last_block_problem().is_some() {
+                repairs.push(format!("{name}: bytes in last block is {0}, more than {BLOCK_SIZE}, it will be set to {BLOCK_SIZE}", entry.bytes_in_last_block));
+                entry.bytes_in_last_block = BLOCK_SIZE as u16;
+            }
+            kept.push(entry);
+        }
+        if repairs.is_empty() {
+            println!("  No entries need repair");
+            return Ok(());
+        }
+        for repair in &repairs {
+            println!("  {repair}");
+        }
+        if !yes && !dry_run && !confirm(&format!("Make these {0} repair(s)?", repairs.len())) {
+            return Err(self.error(ErrorKind::Refused, "Not repaired"));
+        }
+        self.directory.volume.num_files = kept.len() as u16;
+        self.directory.entries.fill(DirectoryEntry::empty());
+        self.directory.entries[..kept.len()].copy_from_slice(&kept);
+        return self.commit_directory(dry_run);
+    }
+
+    // Put first_block_after_directory back to the standard 6, when it's
+    // wrong. The files have to fit in a standard directory for that.
+    pub fn fix_directory_start(&mut self, yes: bool, dry_run: bool) -> Result<(), DiskError> {
+        println!("Checking the directory start on {0}", self.image);
+        let Some(problem) = self.directory_start_problem() else {
+            println!("  First block after directory is {0}, nothing to fix", self.directory.volume.first_block_after_directory);
+            return Ok(());
+        };
+        println!("  {problem}");
+        let capacity = (DIRECTORY_END - 2) * BLOCK_SIZE / ENTRY_SIZE - 1;
+        if self.num_entries() > capacity {
+            return Err(self.error(ErrorKind::NoSpace,
+                format!("{0} files won't fit in a standard directory of {capacity}", self.num_entries())));
+        }
+        println!("  First block after directory will be set to {DIRECTORY_END}");
+        if !yes && !dry_run && !confirm(&format!("Set the first block after the directory to {DIRECTORY_END}?")) {
+            return Err(self.error(ErrorKind::Refused, "Not repaired"));
+        }
+        self.directory.volume.first_block_after_directory = DIRECTORY_END as u16;
+        self.directory_end = DIRECTORY_END;
+        self.directory.entries.truncate(capacity);
+        return self.commit_directory(dry_run);
+    }
+
+    // Overwrite bytes starting at offset within a block of the volume. The
+    // directory is re-read afterwards, so a patch to it isn't undone when the
+    // directory is written back on save.
+    pub fn patch(&mut self, block: usize, offset: usize, bytes: &str, force: bool, dry_run: bool) -> Result<(), DiskError> {
+        let bytes = parse_hex_bytes(bytes).map_err(|message| self.error(ErrorKind::Usage, message))?;
+        let start = block * BLOCK_SIZE + offset;
+        let end = start + bytes.len();
+        if end > self.volume_end() * BLOCK_SIZE {
+            return Err(self.error(ErrorKind::Usage,
+                format!("Bytes {start}..{end} run past the end of the {0}-block volume", self.volume_end())));
+        }
+        println!("Patching {0} bytes at block {block}, offset {offset} on {1}", bytes.len(), self.image);
+        let absolute = self.base * BLOCK_SIZE;
+        let hex = |b: &[u8]| b.iter().map(|b| format!("{b:02x}")).collect::<Vec<String>>().join(" ");
+        println!("  Before: {}", hex(&self.blocks[absolute + start..absolute + end]));
+        println!("  After:  {}", hex(&bytes));
+        if !force {
+            return Err(self.error(ErrorKind::Refused, "Patching writes raw bytes with no checks, so it needs --force"));
+        }
+        self.blocks[absolute + start..absolute + end].copy_from_slice(&bytes);
+        let directory = (self.base + 2) * BLOCK_SIZE..(self.base + self.directory_end) * BLOCK_SIZE;
+        self.directory = Directory::new(&self.blocks[directory]);
+        return self.commit(dry_run);
+    }
+
+    // Copy count blocks of another image's volume, starting at from, over
+    // this volume's blocks starting at at. Like patch, nothing about either
+    // directory is checked, and the directory is re-read afterwards in case
+    // the copy covered it.
+    pub fn copy_blocks(&mut self, source: &AppleDisk, from: usize, count: usize, at: usize, force: bool, dry_run: bool) -> Result<(), DiskError> {
+        if from + count > source.volume_end() {
+            return Err(self.error(ErrorKind::Usage,
+                format!("Blocks {from}..{0} run past the end of the {1}-block volume in {2}", from + count, source.volume_end(), source.image)));
+        }
+        if at + count > self.volume_end() {
+            return Err(self.error(ErrorKind::Usage,
+                format!("Blocks {at}..{0} run past the end of the {1}-block volume", at + count, self.volume_end())));
+        }
+        println!("Copying blocks {from}..{0} of {1} to blocks {at}..{2} of {3}", from + count, source.image, at + count, self.image);
+        if !force {
+            return Err(self.error(ErrorKind::Refused, "Copying blocks writes them with no checks, so it needs --force"));
+        }
+        let start = (self.base + at) * BLOCK_SIZE;
+        self.blocks[start..start + count * BLOCK_SIZE].copy_from_slice(source.read_blocks(from, count));
+        let directory = (self.base + 2) * BLOCK_SIZE..(self.base + self.directory_end) * BLOCK_SIZE;
+        self.directory = Directory::new(&self.blocks[directory]);
+        return self.commit(dry_run);
+    }
+
+    pub fn dump(&self, from: usize, to: usize, count: bool, encoding: Encoding) -> Result<(), DiskError> {
+        if from > to {
+            return Err(self.error(ErrorKind::Usage, format!("from ({from}) must be less than to ({to})")));
+        }
+        if to >= self.volume_end() {
+            return Err(self.error(ErrorKind::Usage, format!("to ({to}) must be less than {0} blocks", self.volume_end())));
+        }
+        if from > self.volume_end() {
+            return Err(self.error(ErrorKind::Usage, format!("from ({from}) must be less than {0} blocks", self.volume_end())));
+        }
+        println!("Dumping contexts of {0} from block {1} to {2}", self.image, from, to);
+        let mut zero_bytes = 0;
+        let mut zero_blocks = Vec::new();
+        for block_no in from..=to {
+            let block = self.read_blocks(block_no, 1);
+            let zeros = block.iter().filter(|b| **b == 0).count();
+            zero_bytes += zeros;
+            if zeros == block.len() {
+                zero_blocks.push(block_no);
+            }
+            print_block(block_no, block, encoding, self.color);
+        }
+        if count {
+            let total = (to - from + 1) * BLOCK_SIZE;
+            println!("Dumped {total} bytes from blocks {from} to {to}: {zero_bytes} zero, {0} non-zero",
+                total - zero_bytes);
+            if zero_blocks.is_empty() {
+                println!("No blocks were all zeros");
+            } else {
+                println!("All-zero blocks: {}", format_block_ranges(&zero_blocks));
+            }
+        }
+        return Ok(());
+    }
+}
+
+// Ask a yes/no question on the terminal. Anything but yes is a no.
+fn confirm(question: &str) -> bool {
+    print!("{question} [y/N] ");
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    let _ = std::io::stdin().read_line(&mut answer);
+    return matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+}
+
+// In colour, zero bytes are dimmed and bytes with no character to show
+// them as are yellow, so text and padding stand out from binary data.
+fn print_block(block_no: usize, block: &[u8], encoding: Encoding, color: bool) {
+    let byte_color = |val: u8| {
+        if val == 0 {
+            Some(Color::Dim)
+        } else if encoding.display(val) == '.' && val != b'.' {
+            Some(Color::Yellow)
+        } else {
+            None
+        }
+    };
+    let paint = |text: String, val: u8| match byte_color(val) {
+        Some(c) => color::paint(&text, c, color),
+        None => text,
+    };
+    for (line, bytes) in block.chunks(hexdump::LINE_LEN).enumerate() {
+        let offset: usize = block_no * BLOCK_SIZE + line * hexdump::LINE_LEN;
+        println!("{}", hexdump::line(offset, bytes,
+            |val| paint(hexdump::hex(val), val),
+            |val| paint(encoding.display(val).to_string(), val)));
+    }
+    println!()
+}
+
+// Disk images under dir, at any depth, in a stable order.
+fn find_images(dir: &Path, images: &mut Vec<std::path::PathBuf>) -> Result<(), DiskError> {
+    let mut entries: Vec<std::path::PathBuf> = fs::read_dir(dir)
+        .map_err(|e| DiskError::from(e).with("path", dir.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .collect();
+    entries.sort();
+    for path in entries {
+        let name = path.to_string_lossy().to_lowercase();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        let extension = Path::new(name).extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+        if path.is_dir() {
+            find_images(&path, images)?;
+        } else if ["dsk", "do", "d13", "po", "zip"].contains(&extension.as_str()) {
+            images.push(path);
+        }
+    }
+    return Ok(());
+}
+
+// Totals over every image in a directory tree, for cataloguing a
+// collection. Images that can't be read are counted and skipped.
+pub fn stats(dir: &str, options: &DiskOptions) -> Result<(), DiskError> {
+    let mut paths = Vec::new();
+    find_images(Path::new(dir), &mut paths)?;
+    let mut volumes = 0;
+    let mut unreadable = 0;
+    let mut files = 0;
+    let mut blocks = 0;
+    let mut kinds: std::collections::BTreeMap<&str, usize> = std::collections::BTreeMap::new();
+    let mut names: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    let mut dates: Vec<(i32, u32, u32, u16)> = Vec::new();
+    for path in &paths {
+        let name = path.display().to_string();
+        let disk = match fs::read(path).map_err(|e| DiskError::from(e).with("image", &name))
+            .and_then(|contents| AppleDisk::directory_only(&name, contents, options)) {
+            Ok(disk) => disk,
+            Err(error) => {
+                eprintln!("Warning: skipping {name}: {error}");
+                unreadable += 1;
+                continue;
+            }
+        };
+        volumes += 1;
+        for entry in &disk.directory.entries[..disk.num_entries()] {
+            files += 1;
+            if let Some(range) = entry.block_range(disk.declared_end()) {
+                blocks += range.len();
+            }
+            let kind = FileKind::from_type(entry.file_type).map_or("unknown", |k| k.name());
+            *kinds.entry(kind).or_default() += 1;
+            *names.entry(pstring_to_string(&entry.name)).or_default() += 1;
+            if pdate_is_valid(entry.date) {
+                let ydm = PdateYDM::new(entry.date);
+                dates.push((ydm.year, ydm.month, ydm.day, entry.date));
+            }
+        }
+    }
+    println!("Statistics for {dir}");
+    println!("Volumes: {volumes}");
+    if unreadable > 0 {
+        println!("Unreadable images: {unreadable}");
+    }
+    println!("Files:   {files}, using {blocks} blocks");
+    if !kinds.is_empty() {
+        println!("File types:");
+        for (kind, count) in &kinds {
+            println!("  {kind:21} {count:6}");
+        }
+    }
+    let mut common: Vec<(String, usize)> = names.into_iter().filter(|(_, n)| *n > 1).collect();
+    common.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    if !common.is_empty() {
+        println!("Most common names:");
+        for (name, count) in common.iter().take(10) {
+            println!("  {name:21} {count:6}");
+        }
+    }
+    dates.sort();
+    if let (Some(first), Some(last)) = (dates.first(), dates.last()) {
+        println!("Dates:   {0} to {1}", pdate_to_string(first.3), pdate_to_string(last.3));
+    }
+    return Ok(());
+}
+
+// Dump blocks as they're added to an image that's still being written, like
+// tail -f. The image may not have a directory yet, so it isn't opened as a
+// volume. DOS-ordered images can only be de-interleaved a track at a time,
+// so their blocks appear in groups of eight.
+pub fn follow(name: &str, options: &DiskOptions, from: usize, encoding: Encoding, interval: f64) -> Result<(), DiskError> {
+    if name == "-" || options.prodos_path.is_some() {
+        return Err(DiskError::new(ErrorKind::Usage, "--follow needs an image file, and can't be used with --prodos-path")
+            .with("image", name));
+    }
+    let order = options.interleave.clone().unwrap_or_else(|| SectorOrder::from_image_name(name));
+    let layout = Layout::new(&order, &options.geometry.for_image(name)).map_err(|e| e.with("image", name))?;
+    let unit = layout.track_len().unwrap_or(BLOCK_SIZE);
+    let mut next = from;
+    println!("Following {name} from block {from}, interrupt to stop");
+    loop {
+        let length = fs::metadata(name).map_err(|e| DiskError::from(e).with("image", name))?.len() as usize;
+        let available = (length / unit * unit / BLOCK_SIZE).saturating_sub(options.offset);
+        if available > next {
+            let mut contents = fs::read(name).map_err(|e| DiskError::from(e).with("image", name))?;
+            contents.truncate((available + options.offset) * BLOCK_SIZE);
+            let blocks = layout.deinterleave(&contents);
+            for block_no in next..available {
+                let start = (options.offset + block_no) * BLOCK_SIZE;
+                print_block(block_no, &blocks[start..start + BLOCK_SIZE], encoding, options.color);
+            }
+            next = available;
+        }
+        std::thread::sleep(std::time::Duration::from_secs_f64(interval));
+    }
+}
+
+// Re-read the image and list it again whenever its modification time
+// changes, so another tool's writes show up. An image caught half-written
+// may not parse; that's reported and the next change tried instead.
+pub fn watch(name: &str, options: &DiskOptions, interval: f64) -> Result<(), DiskError> {
+    if name == "-" {
+        return Err(DiskError::new(ErrorKind::Usage, "watch needs an image file, not standard input").with("image", name));
+    }
+    println!("Watching {name}, interrupt to stop");
+    let mut seen = None;
+    loop {
+        let modified = fs::metadata(archive::split_member(name).0).and_then(|m| m.modified()).map_err(|e| DiskError::from(e).with("image", name))?;
+        if seen != Some(modified) {
+            if seen.is_some() {
+                println!();
+                println!("{name} changed, re-reading it");
+            }
+            match fs::read(archive::split_member(name).0).map_err(|e| DiskError::from(e).with("image", name))
+                .and_then(|contents| AppleDisk::from_bytes(name, contents, options)) {
+                Ok(disk) => disk.list_short(0, None, None, false),
+                Err(error) => eprintln!("Error: {error}"),
+            }
+            seen = Some(modified);
+        }
+        std::thread::sleep(std::time::Duration::from_secs_f64(interval));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every day a Pascal date can hold reads back as itself, including both
+    // ends of the 1970-2069 window and the change of century in between.
+    #[test]
+    fn encode_pdate_round_trips() {
+        let mut date = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+        let last = NaiveDate::from_ymd_opt(2069, 12, 31).unwrap();
+        while date <= last {
+            let pdate = encode_pdate(date.year(), date.month(), date.day()).unwrap();
+            assert_eq!(pdate_to_string(pdate), date.format("%Y-%m-%d").to_string());
+            date = date.succ_opt().unwrap();
+        }
+        assert!(encode_pdate(1969, 12, 31).is_err());
+        assert!(encode_pdate(2070, 1, 1).is_err());
+    }
+
+    // Renaming the volume changes only the name's bytes: the header's
+    // reserved bytes and whatever follows the last entry come back as they
+    // were read.
+    #[test]
+    fn directory_write_keeps_unknown_bytes() {
+        let mut bytes: Vec<u8> = (0..4 * BLOCK_SIZE).map(|i| (i * 7 % 251) as u8).collect();
+        bytes[..ENTRY_SIZE].fill(0);
+        bytes[2] = 6;
+        bytes[6..10].copy_from_slice(b"\x03OLD");
+        bytes[18..26].copy_from_slice(&[0x34, 0x12, 0x4f, 0xa8, 1, 2, 3, 4]);
+        let mut directory = Directory::new(&bytes);
+        directory.volume.volume_name = string_to_pstring("RENAMED");
+        let mut written = bytes.clone();
+        directory.write_to(&mut written);
+        assert_eq!(&written[6..14], b"\x07RENAMED");
+        assert_eq!(&written[..6], &bytes[..6]);
+        assert_eq!(&written[14..], &bytes[14..]);
+    }
+
+    // A 13-sector image read through the DOS 3.2 map and written back comes
+    // out byte-for-byte as it went in, with each volume sector read from
+    // where the map says.
+    #[test]
+    fn d13_interleave_round_trips() {
+        let layout = Layout::standard("test.d13");
+        assert_eq!(layout.track_len(), Some(13 * 256));
+        let image: Vec<u8> = (0..35 * 13 * 256).map(|i| ((i / 256) ^ (i * 31)) as u8).collect();
+        let blocks = layout.deinterleave(&image);
+        assert_eq!(blocks.len(), image.len());
+        for n in [0, 1, 12, 13, 14, 35 * 13 - 1] {
+            let offset = (n / 13) * 13 * 256 + SECTOR_MAP_13[n % 13] * 256;
+            assert_eq!(&blocks[n * 256..(n + 1) * 256], &image[offset..offset + 256]);
+        }
+        assert_eq!(layout.interleave(&blocks), image);
     }
 }
@@ -0,0 +1,169 @@
+// Just enough of ProDOS to find a file on a hard-disk image, for Pascal
+// volumes that are kept inside a ProDOS file (e.g. /PASCAL.VOL on a .hdv).
+//
+// Directory blocks start with previous/next block pointers, followed by fixed
+// size entries. The first entry of a directory's key block is its header,
+// which records the entry size and the number of entries per block.
+
+use super::BLOCK_SIZE;
+
+const VOLUME_DIRECTORY_BLOCK: usize = 2;
+
+const SEEDLING: u8 = 1;
+const SAPLING: u8 = 2;
+const TREE: u8 = 3;
+const SUBDIRECTORY: u8 = 0xd;
+
+// Every ProDOS directory has entries of this size. A header claiming less
+// can't hold the fields read from each entry.
+const ENTRY_LENGTH: usize = 0x27;
+
+struct FileEntry {
+    storage_type: u8,
+    name: String,
+    key_pointer: usize,
+    eof: usize,
+}
+
+fn word(image: &[u8], offset: usize) -> usize {
+    return image[offset] as usize | (image[offset + 1] as usize) << 8;
+}
+
+fn block(image: &[u8], index: usize) -> Result<&[u8], String> {
+    let start = index * BLOCK_SIZE;
+    if start + BLOCK_SIZE > image.len() {
+        return Err(format!("ProDOS block {index} is past the end of the image"));
+    }
+    return Ok(&image[start..start + BLOCK_SIZE]);
+}
+
+fn read_directory(image: &[u8], key_block: usize) -> Result<Vec<FileEntry>, String> {
+    let key = block(image, key_block)?;
+    let entry_length = key[4 + 0x1f] as usize;
+    let entries_per_block = key[4 + 0x20] as usize;
+    if entry_length < ENTRY_LENGTH || 4 + entry_length * entries_per_block > BLOCK_SIZE {
+        return Err(format!("block {key_block} doesn't look like a ProDOS directory"));
+    }
+    let mut entries = Vec::new();
+    let mut current = key_block;
+    let mut visited = 0;
+    while current != 0 {
+        let bytes = block(image, current)?;
+        // The header occupies the first slot of the key block.
+        let first = if current == key_block { 1 } else { 0 };
+        for slot in first..entries_per_block {
+            let e = &bytes[4 + slot * entry_length..4 + (slot + 1) * entry_length];
+            let storage_type = e[0] >> 4;
+            if storage_type == 0 {
+                continue;
+            }
+            let name_length = (e[0] & 0x0f) as usize;
+            entries.push(FileEntry {
+                storage_type,
+                name: String::from_utf8_lossy(&e[1..1 + name_length]).to_string(),
+                key_pointer: word(e, 0x11),
+                eof: word(e, 0x15) | (e[0x17] as usize) << 16,
+            });
+        }
+        current = word(bytes, 2);
+        visited += 1;
+        if visited > image.len() / BLOCK_SIZE {
+            return Err(format!("directory at block {key_block} has a looping block chain"));
+        }
+    }
+    return Ok(entries);
+}
+
+// Index blocks hold the low bytes of 256 block numbers, then the high bytes.
+fn index_entries(image: &[u8], index_block: usize) -> Result<Vec<usize>, String> {
+    let bytes = block(image, index_block)?;
+    return Ok((0..256).map(|i| bytes[i] as usize | (bytes[256 + i] as usize) << 8).collect());
+}
+
+fn data_blocks(image: &[u8], entry: &FileEntry) -> Result<Vec<usize>, String> {
+    let count = entry.eof.div_ceil(BLOCK_SIZE);
+    let mut blocks = match entry.storage_type {
+        SEEDLING => vec![entry.key_pointer],
+        SAPLING => index_entries(image, entry.key_pointer)?,
+        TREE => {
+            let mut blocks = Vec::new();
+            for index_block in index_entries(image, entry.key_pointer)? {
+                if blocks.len() >= count {
+                    break;
+                }
+                blocks.extend(index_entries(image, index_block)?);
+            }
+            blocks
+        }
+        other => return Err(format!("{} has unsupported storage type {other}", entry.name)),
+    };
+    blocks.truncate(count);
+    return Ok(blocks);
+}
+
+// Find the file at `path` and return the first block and length in blocks of
+// its data. The Pascal volume is read in place, so the file has to be stored
+// in consecutive blocks.
+pub fn find_contiguous_file(image: &[u8], path: &str) -> Result<(usize, usize), String> {
+    let mut components: Vec<String> = path.split('/').filter(|c| !c.is_empty()).map(|c| c.to_uppercase()).collect();
+    if components.is_empty() {
+        return Err(format!("{path} doesn't name a ProDOS file"));
+    }
+    // Allow a full pathname that starts with the volume name.
+    let volume = block(image, VOLUME_DIRECTORY_BLOCK)?;
+    let volume_name = String::from_utf8_lossy(&volume[5..5 + (volume[4] & 0x0f) as usize]).to_uppercase();
+    if components.len() > 1 && components[0] == volume_name {
+        components.remove(0);
+    }
+    let mut directory = VOLUME_DIRECTORY_BLOCK;
+    for (i, component) in components.iter().enumerate() {
+        let entries = read_directory(image, directory)?;
+        let Some(entry) = entries.iter().find(|e| e.name.to_uppercase() == *component) else {
+            return Err(format!("{component} not found in ProDOS path {path}"));
+        };
+        if i + 1 < components.len() {
+            if entry.storage_type != SUBDIRECTORY {
+                return Err(format!("{component} in ProDOS path {path} is not a directory"));
+            }
+            directory = entry.key_pointer;
+            continue;
+        }
+        let blocks = data_blocks(image, entry)?;
+        let Some(&first) = blocks.first() else {
+            return Err(format!("{path} is empty"));
+        };
+        for (n, b) in blocks.iter().enumerate() {
+            if *b != first + n {
+                return Err(format!("{path} isn't stored in consecutive blocks (block {n} is at {b})"));
+            }
+        }
+        return Ok((first, blocks.len()));
+    }
+    unreachable!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An image whose volume directory key block has a header, and no files,
+    // with this entry length.
+    fn image(entry_length: u8) -> Vec<u8> {
+        let mut image = vec![0u8; 4 * BLOCK_SIZE];
+        let key = &mut image[VOLUME_DIRECTORY_BLOCK * BLOCK_SIZE..];
+        key[4] = 0xf4;
+        key[5..9].copy_from_slice(b"TEST");
+        key[4 + 0x1f] = entry_length;
+        key[4 + 0x20] = 13;
+        return image;
+    }
+
+    #[test]
+    fn short_entries_are_rejected() {
+        assert!(read_directory(&image(0x27), VOLUME_DIRECTORY_BLOCK).unwrap().is_empty());
+        for entry_length in [0, 1, 0x17, 0x26] {
+            let error = read_directory(&image(entry_length), VOLUME_DIRECTORY_BLOCK).err().expect("should be rejected");
+            assert!(error.contains("doesn't look like a ProDOS directory"), "{error}");
+        }
+    }
+}
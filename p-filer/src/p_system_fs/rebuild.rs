@@ -0,0 +1,114 @@
+// Last-resort recovery for a volume whose directory is gone: scan the data
+// blocks for the starts of files whose formats can be recognised, and offer
+// a new directory listing them.
+//
+// Code files start with a segment dictionary whose segments all fit in the
+// blocks that follow. Textfiles are a header page followed by pages of
+// CR-terminated lines, NUL-padded at the end of each page. Anything else,
+// data files included, can't be told from free space, so it's left out.
+
+use std::ops::Range;
+use p_code::codefile::{is_text_page, ByteOrder, Codefile};
+use super::{confirm, string_to_pstring, AppleDisk, DirectoryEntry, DiskError, ErrorKind, BLOCK_SIZE, CODE_FILE, TEXT_FILE};
+
+struct Found {
+    blocks: Range<usize>,
+    file_type: u16,
+    why: String,
+}
+
+impl AppleDisk {
+    // The dictionary block alone says how long the file would be, in either
+    // byte order, so only that much is read to check it.
+    fn find_code_file(&self, block: usize, end: usize) -> Option<Found> {
+        for order in [ByteOrder::Le, ByteOrder::Be] {
+            let dictionary = Codefile::from_bytes("", self.read_blocks(block, 1).to_vec(), order).ok()?;
+            let Some(length) = dictionary.segments().iter().map(|s| s.end().div_ceil(BLOCK_SIZE)).max() else {
+                continue;
+            };
+            if block + length > end {
+                continue;
+            }
+            let codefile = Codefile::from_bytes("", self.read_blocks(block, length).to_vec(), order).ok()?;
+            if !codefile.is_plausible() {
+                continue;
+            }
+            let names: Vec<String> = codefile.segments().iter().map(|s| s.name.trim().to_string()).collect();
+            return Some(Found {
+                blocks: block..block + length,
+                file_type: CODE_FILE,
+                why: format!("segment dictionary for {}", names.join(", ")),
+            });
+        }
+        return None;
+    }
+
+    // A textfile starts with a header page that isn't text itself.
+    fn find_text_file(&self, block: usize, end: usize) -> Option<Found> {
+        let page_blocks = self.text_page_size / BLOCK_SIZE;
+        let is_text = |b: usize| b + page_blocks <= end && is_text_page(self.read_blocks(b, page_blocks));
+        let first = block + page_blocks;
+        if is_text(block) || !is_text(first) {
+            return None;
+        }
+        let mut after = first;
+        while is_text(after) {
+            after += page_blocks;
+        }
+        return Some(Found {
+            blocks: block..after,
+            file_type: TEXT_FILE,
+            why: format!("header page and {0} page(s) of text", (after - first) / page_blocks),
+        });
+    }
+
+    // Propose a directory of every file found, and write it after asking.
+    // The volume's name, size and date are kept.
+    pub fn rebuild_directory(&mut self, yes: bool, dry_run: bool) -> Result<(), DiskError> {
+        println!("Scanning {0} for files", self.image);
+        let end = self.volume_end();
+        let mut found = Vec::new();
+        let mut block = self.directory_end;
+        while block < end {
+            match self.find_code_file(block, end).or_else(|| self.find_text_file(block, end)) {
+                Some(file) => {
+                    block = file.blocks.end;
+                    found.push(file);
+                }
+                None => block += 1,
+            }
+        }
+        let capacity = self.directory.entries.len();
+        if found.len() > capacity {
+            return Err(self.error(ErrorKind::NoSpace,
+                format!("found {0} files, but the directory only has room for {capacity}", found.len())));
+        }
+        if found.is_empty() {
+            return Err(self.error(ErrorKind::NotFound, "No code or text files found"));
+        }
+        let mut entries = Vec::new();
+        for (n, file) in found.iter().enumerate() {
+            let extension = if file.file_type == CODE_FILE { "CODE" } else { "TEXT" };
+            let name = format!("RECOVER{0}.{extension}", n + 1);
+            println!("  {name:15} blocks {0}..{1}: {2}", file.blocks.start, file.blocks.end, file.why);
+            entries.push(DirectoryEntry {
+                first_block: file.blocks.start as u16,
+                first_after_block: file.blocks.end as u16,
+                file_type: file.file_type,
+                name: string_to_pstring(&name),
+                bytes_in_last_block: BLOCK_SIZE as u16,
+                date: self.directory.volume.date,
+            });
+        }
+        println!("  Anything else, including data files, is left as free space");
+        println!("  The directory's {0} entries will be replaced with these {1}", self.num_entries(), entries.len());
+        if !yes && !dry_run && !confirm(&format!("Write a new directory of {0} file(s)?", entries.len())) {
+            return Err(self.error(ErrorKind::Refused, "Directory not rebuilt"));
+        }
+        self.directory.volume.first_block_after_directory = self.directory_end as u16;
+        self.directory.volume.num_files = entries.len() as u16;
+        self.directory.entries.fill(DirectoryEntry::empty());
+        self.directory.entries[..entries.len()].copy_from_slice(&entries);
+        return self.commit_directory(dry_run);
+    }
+}